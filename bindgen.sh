@@ -1,3 +1,10 @@
+# Regenerates spng-sys/src/ffi.rs from the libspng submodule. Run from the repository root:
+#
+#   cargo install bindgen-cli
+#   sh bindgen.sh
+#
+# The generated enum constants are checked at compile time in spng-sys/src/lib.rs. If those
+# assertions fail after regenerating, the `spng` crate's enums need updating to match.
 VERSION=$(bindgen --version)
 bindgen -o spng-sys/src/ffi.rs \
  --raw-line "/* ${VERSION} */" \
@@ -13,5 +13,73 @@ fn png_decode(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, png_decode);
+fn png_encode_with(
+    c: &mut Criterion,
+    name: &str,
+    compression: png::Compression,
+    filter: png::FilterType,
+    adaptive_filter: png::AdaptiveFilterType,
+) {
+    let d = png::Decoder::new(spng_benchmarks::TEST_PNG_002);
+    let mut reader = d.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    c.bench_function(name, |b| {
+        let mut buf = Vec::new();
+        b.iter(|| {
+            buf.clear();
+            let mut encoder = png::Encoder::new(&mut buf, info.width, info.height);
+            encoder.set_color(info.color_type);
+            encoder.set_depth(info.bit_depth);
+            encoder.set_compression(compression);
+            encoder.set_filter(filter);
+            encoder.set_adaptive_filter(adaptive_filter);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&data).unwrap();
+            writer.finish().unwrap();
+            black_box(buf.len());
+        })
+    });
+}
+
+fn png_encode(c: &mut Criterion) {
+    use png::{AdaptiveFilterType::*, Compression, FilterType};
+    png_encode_with(
+        c,
+        "png_encode_fast",
+        Compression::Fast,
+        FilterType::Sub,
+        Adaptive,
+    );
+    png_encode_with(
+        c,
+        "png_encode_default",
+        Compression::Default,
+        FilterType::Sub,
+        Adaptive,
+    );
+    png_encode_with(
+        c,
+        "png_encode_best",
+        Compression::Best,
+        FilterType::Sub,
+        Adaptive,
+    );
+    png_encode_with(
+        c,
+        "png_encode_default_unfiltered",
+        Compression::Default,
+        FilterType::NoFilter,
+        NonAdaptive,
+    );
+    png_encode_with(
+        c,
+        "png_encode_default_paeth",
+        Compression::Default,
+        FilterType::Paeth,
+        NonAdaptive,
+    );
+}
+
+criterion_group!(benches, png_decode, png_encode);
 criterion_main!(benches);
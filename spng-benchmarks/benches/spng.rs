@@ -13,5 +13,171 @@ fn spng_decode(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, spng_decode);
+fn spng_decode_format(c: &mut Criterion, name: &str, format: spng::Format) {
+    c.bench_function(name, |b| {
+        let mut buf = Vec::new();
+        b.iter(|| {
+            let d = spng::Decoder::new(spng_benchmarks::TEST_PNG_002).with_output_format(format);
+            let mut reader = d.read_info().unwrap();
+            spng_benchmarks::reserve(&mut buf, reader.output_buffer_size());
+            let _info = reader.next_frame(&mut buf).unwrap();
+            black_box(reader);
+        })
+    });
+}
+
+fn spng_decode_native(c: &mut Criterion) {
+    spng_decode_format(c, "spng_decode_rgba8_native", spng::Format::Rgba8);
+}
+
+fn spng_decode_converted(c: &mut Criterion) {
+    spng_decode_format(c, "spng_decode_rgb8_converted", spng::Format::Rgb8);
+}
+
+/// Compares decoding to the copy of the decoded image that a zero-copy decode would avoid
+fn spng_decode_copy(c: &mut Criterion) {
+    let image = spng::decode_bytes(spng_benchmarks::TEST_PNG_002, spng::Format::Png).unwrap();
+    c.bench_function("spng_decode_png", |b| {
+        b.iter(|| {
+            black_box(spng::decode_bytes(spng_benchmarks::TEST_PNG_002, spng::Format::Png).unwrap())
+        })
+    });
+    c.bench_function("spng_decode_copy", |b| {
+        b.iter(|| black_box(image.data.clone()))
+    });
+}
+
+fn spng_decode_rows(c: &mut Criterion) {
+    c.bench_function("spng_decode_rows", |b| {
+        b.iter(|| {
+            let d = spng::Decoder::new(spng_benchmarks::TEST_PNG_002);
+            let mut reader = d.read_info().unwrap();
+            let mut rows = reader.rows().unwrap();
+            while let Some(row) = rows.next_row() {
+                black_box(row.unwrap());
+            }
+        })
+    });
+}
+
+fn spng_decode_rows_buffered(c: &mut Criterion) {
+    c.bench_function("spng_decode_rows_buffered", |b| {
+        b.iter(|| {
+            let d = spng::Decoder::new(spng_benchmarks::TEST_PNG_002);
+            let mut reader = d.read_info().unwrap();
+            for row in reader.rows_buffered().unwrap() {
+                black_box(row.unwrap());
+            }
+        })
+    });
+}
+
+fn spng_encode_with(
+    c: &mut Criterion,
+    name: &str,
+    compression_level: i32,
+    filter_choice: spng::FilterChoice,
+) {
+    let (out_info, data) =
+        spng::decode(spng_benchmarks::TEST_PNG_002, spng::Format::Rgba8).unwrap();
+    c.bench_function(name, |b| {
+        let mut buf = Vec::new();
+        b.iter(|| {
+            buf.clear();
+            let mut writer = spng::Encoder::new(&mut buf, 0, 0)
+                .with_info(&out_info)
+                .with_compression_level(compression_level)
+                .with_filter_choice(filter_choice)
+                .write_info()
+                .unwrap();
+            writer.write_image_data(&data).unwrap();
+            black_box(writer.finish().unwrap());
+        })
+    });
+}
+
+fn spng_encode(c: &mut Criterion) {
+    use spng::FilterChoice;
+    spng_encode_with(c, "spng_encode_fast", 1, FilterChoice::ALL);
+    spng_encode_with(c, "spng_encode_default", 6, FilterChoice::ALL);
+    spng_encode_with(c, "spng_encode_best", 9, FilterChoice::ALL);
+    spng_encode_with(
+        c,
+        "spng_encode_default_unfiltered",
+        6,
+        FilterChoice::empty(),
+    );
+    spng_encode_with(c, "spng_encode_default_paeth", 6, FilterChoice::PAETH);
+}
+
+fn spng_encode_file(c: &mut Criterion) {
+    let (out_info, data) =
+        spng::decode(spng_benchmarks::TEST_PNG_002, spng::Format::Rgba8).unwrap();
+    let path = std::env::temp_dir().join("spng_encode_file.png");
+    c.bench_function("spng_encode_file", |b| {
+        b.iter(|| {
+            let file = std::fs::File::create(&path).unwrap();
+            let mut writer = spng::Encoder::new(file, 0, 0)
+                .with_info(&out_info)
+                .write_info()
+                .unwrap();
+            writer.write_image_data(&data).unwrap();
+            black_box(writer.finish().unwrap());
+        })
+    });
+    c.bench_function("spng_encode_file_buffered", |b| {
+        b.iter(|| {
+            let mut writer = spng::Encoder::create(&path, 0, 0)
+                .unwrap()
+                .with_info(&out_info)
+                .write_info()
+                .unwrap();
+            writer.write_image_data(&data).unwrap();
+            black_box(writer.finish().unwrap());
+        })
+    });
+    let _ = std::fs::remove_file(&path);
+}
+
+fn spng_encode_reserve(c: &mut Criterion) {
+    // Tile the test image 4x4 to get a large image
+    let (info, data) = spng::decode(spng_benchmarks::TEST_PNG_002, spng::Format::Rgba8).unwrap();
+    let line_size = info.line_size();
+    let mut large = Vec::with_capacity(data.len() * 16);
+    for _ in 0..4 {
+        for row in data.chunks_exact(line_size) {
+            for _ in 0..4 {
+                large.extend_from_slice(row);
+            }
+        }
+    }
+    let (width, height) = (info.width * 4, info.height * 4);
+    let encode = |hint: Option<usize>| {
+        let mut encoder = spng::Encoder::new(Vec::new(), width, height);
+        if let Some(hint) = hint {
+            encoder.reserve(hint);
+        }
+        let mut writer = encoder.write_info().unwrap();
+        writer.write_image_data(&large).unwrap();
+        writer.finish().unwrap()
+    };
+    let hint = encode(None).len();
+    c.bench_function("spng_encode_large", |b| b.iter(|| black_box(encode(None))));
+    c.bench_function("spng_encode_large_reserved", |b| {
+        b.iter(|| black_box(encode(Some(hint))))
+    });
+}
+
+criterion_group!(
+    benches,
+    spng_decode,
+    spng_decode_native,
+    spng_decode_converted,
+    spng_decode_copy,
+    spng_decode_rows,
+    spng_decode_rows_buffered,
+    spng_encode,
+    spng_encode_file,
+    spng_encode_reserve
+);
 criterion_main!(benches);
@@ -1,16 +1,46 @@
 use std::env;
 
 fn main() {
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    if target_arch == "wasm32" && target_os == "unknown" {
+        // libspng and zlib require a C standard library, which wasm32-unknown-unknown lacks
+        panic!("wasm32-unknown-unknown is not supported, use a WASI target such as wasm32-wasip1");
+    }
+
+    // cfg!(target_feature) would report the host's features when cross compiling
+    let target_features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    let has_feature = |name| target_features.split(',').any(|feature| feature == name);
+
     let mut build = cc::Build::new();
     build.file("libspng/spng/spng.c");
     if let Some(libz_include) = env::var_os("DEP_Z_INCLUDE") {
         build.include(libz_include);
     }
-    if cfg!(target_feature = "sse4.1") {
-        build.define("SPNG_SSE", Some("4"));
-    } else if cfg!(target_feature = "ssse3") {
-        build.define("SPNG_SSE", Some("3"));
-    }
+    // libspng has no runtime dispatch for its filter kernels, the SSE level it's compiled with is
+    // used unconditionally. Only use levels the target is guaranteed to have, higher levels
+    // require e.g. `-C target-cpu=native` or `-C target-feature=+sse4.1`.
+    let simd = match target_arch.as_str() {
+        "x86" | "x86_64" if has_feature("sse4.1") => {
+            build.define("SPNG_SSE", Some("4"));
+            "sse4.1"
+        }
+        "x86" | "x86_64" if has_feature("ssse3") => {
+            build.define("SPNG_SSE", Some("3"));
+            "ssse3"
+        }
+        // libspng defaults to SSE2 on x86
+        "x86" | "x86_64" if has_feature("sse2") => "sse2",
+        "x86" | "x86_64" => {
+            // e.g. i586 targets, where the default would emit SSE2 instructions
+            build.define("SPNG_DISABLE_OPT", None);
+            "none"
+        }
+        // libspng uses NEON when the compiler enables it, i.e. `__ARM_NEON` is defined
+        "arm" | "aarch64" if has_feature("neon") => "neon",
+        _ => "none",
+    };
+    println!("cargo:rustc-env=SPNG_SIMD={}", simd);
     build.compile("spng");
 
     // DEP_SPNG_INCLUDE for other crates
@@ -1,4 +1,22 @@
 //! Native bindings to [libspng](https://libspng.org).
+//!
+//! ## Custom streams
+//!
+//! A stream callback of type [`spng_rw_fn`] can be installed with [`spng_set_png_stream`] to
+//! decode from, or encode to, any source. The callback is passed the `user` pointer and must fill
+//! or consume exactly `length` bytes, returning `SPNG_OK`, `SPNG_IO_EOF` at the end of input or
+//! `SPNG_IO_ERROR`. [`spng_read_fn`] and [`spng_write_fn`] are the same type, named for the
+//! direction of the stream.
+//!
+//! ## Encoding to a buffer
+//!
+//! With the `SPNG_ENCODE_TO_BUFFER` option set, an encoder context writes to an internal buffer
+//! that is retrieved with [`spng_get_png_buffer`] once the image is encoded. The returned buffer
+//! is allocated with the context's `malloc_fn`, by default the C library's `malloc`, and is owned
+//! by the caller from then on: it must be released with the matching `free_fn` and is not freed
+//! by [`spng_ctx_free`].
+
+#![no_std]
 
 #[allow(non_upper_case_globals)]
 #[allow(non_camel_case_types)]
@@ -8,6 +26,54 @@ mod ffi;
 
 pub use ffi::*;
 
+/// The SIMD instruction set libspng was compiled to use: `"sse4.1"`, `"ssse3"`, `"sse2"`,
+/// `"neon"` or `"none"`.
+///
+/// This is selected at build time from the target features, libspng does not detect CPU features
+/// at runtime.
+pub const SPNG_SIMD: &str = env!("SPNG_SIMD");
+
+// bindgen emits C enums as `c_int` or `c_uint` constants depending on the platform and compiler,
+// and the `spng` crate casts them to `#[repr(u32)]` and `#[repr(i32)]` enums. Fail the build
+// instead of miscompiling if `ffi.rs` was regenerated with unexpected sizes or values.
+// See `bindgen.sh` for how to regenerate the bindings.
+const _: () = {
+    use core::mem::size_of;
+
+    assert!(size_of::<spng_errno>() == 4);
+    assert!(size_of::<spng_format>() == 4);
+    assert!(size_of::<spng_color_type>() == 4);
+    assert!(size_of::<spng_ctx_flags>() == 4);
+    assert!(size_of::<spng_decode_flags>() == 4);
+    assert!(size_of::<spng_encode_flags>() == 4);
+    assert!(size_of::<spng_crc_action>() == 4);
+    assert!(size_of::<spng_option>() == 4);
+
+    assert!(spng_errno_SPNG_IO_ERROR == -2);
+    assert!(spng_errno_SPNG_IO_EOF == -1);
+    assert!(spng_errno_SPNG_OK == 0);
+    assert!(spng_errno_SPNG_EOI == 75);
+
+    assert!(spng_format_SPNG_FMT_RGBA8 == 1);
+    assert!(spng_format_SPNG_FMT_RGBA16 == 2);
+    assert!(spng_format_SPNG_FMT_RGB8 == 4);
+    assert!(spng_format_SPNG_FMT_GA8 == 16);
+    assert!(spng_format_SPNG_FMT_GA16 == 32);
+    assert!(spng_format_SPNG_FMT_G8 == 64);
+    assert!(spng_format_SPNG_FMT_PNG == 256);
+    assert!(spng_format_SPNG_FMT_RAW == 512);
+
+    assert!(spng_color_type_SPNG_COLOR_TYPE_GRAYSCALE == 0);
+    assert!(spng_color_type_SPNG_COLOR_TYPE_TRUECOLOR == 2);
+    assert!(spng_color_type_SPNG_COLOR_TYPE_INDEXED == 3);
+    assert!(spng_color_type_SPNG_COLOR_TYPE_GRAYSCALE_ALPHA == 4);
+    assert!(spng_color_type_SPNG_COLOR_TYPE_TRUECOLOR_ALPHA == 6);
+
+    assert!(spng_ctx_flags_SPNG_CTX_ENCODER == 2);
+    assert!(spng_decode_flags_SPNG_DECODE_PROGRESSIVE == 256);
+    assert!(spng_encode_flags_SPNG_ENCODE_FINALIZE == 2);
+};
+
 // Declaring this crate as extern is needed so that the Rust compiler thinks libz
 // is used, and thus passes the expected parameters to get libz linked in. See:
 // https://github.com/dtolnay/link-cplusplus/blob/75a186c35babbb7b39d0e5c544e1dfc9cc704800/README.md?plain=1#L54-L62
@@ -15,10 +81,59 @@ extern crate libz_sys;
 
 #[test]
 fn create_context() {
-    use std::ptr;
+    use core::ptr;
+    unsafe {
+        let ctx = spng_ctx_new(0);
+        assert_ne!(ptr::null_mut(), ctx);
+        spng_ctx_free(ctx);
+    }
+}
+
+#[test]
+fn custom_read_callback() {
+    use core::{ptr, slice};
+
+    // A 1x1 RGBA8 image
+    static PNG: [u8; 70] = [
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1f,
+        0x15, 0xc4, 0x89, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0x10,
+        0x54, 0x32, 0x76, 0x01, 0x00, 0x01, 0x59, 0x00, 0xab, 0xcc, 0x74, 0x37, 0xbb, 0x00, 0x00,
+        0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+    ];
+
+    struct Cursor {
+        data: &'static [u8],
+        pos: usize,
+    }
+
+    unsafe extern "C" fn read(
+        _: *mut spng_ctx,
+        user: *mut libc::c_void,
+        dest: *mut libc::c_void,
+        length: usize,
+    ) -> libc::c_int {
+        let cursor = &mut *(user as *mut Cursor);
+        let src = match cursor.data.get(cursor.pos..cursor.pos + length) {
+            Some(src) => src,
+            None => return spng_errno_SPNG_IO_EOF,
+        };
+        slice::from_raw_parts_mut(dest as *mut u8, length).copy_from_slice(src);
+        cursor.pos += length;
+        spng_errno_SPNG_OK
+    }
+
+    let mut cursor = Cursor { data: &PNG, pos: 0 };
+    let mut out = [0u8; 4];
     unsafe {
         let ctx = spng_ctx_new(0);
         assert_ne!(ptr::null_mut(), ctx);
+        let user = &mut cursor as *mut Cursor as *mut libc::c_void;
+        assert_eq!(0, spng_set_png_stream(ctx, Some(read), user));
+        let fmt = spng_format_SPNG_FMT_RGBA8 as libc::c_int;
+        let ret = spng_decode_image(ctx, out.as_mut_ptr() as _, out.len(), fmt, 0);
         spng_ctx_free(ctx);
+        assert_eq!(0, ret);
     }
+    assert_eq!([0x11, 0x22, 0x33, 0x44], out);
 }
@@ -0,0 +1,221 @@
+use std::{cell::Cell, ptr};
+
+use spng_sys as sys;
+
+use crate::{raw::RawContext, ContextFlags, DecodeFlags, Error, Format, Info, OutputInfo};
+
+thread_local! {
+    /// The arena serving allocations for `decode_with_arena` on this thread.
+    ///
+    /// libspng's allocation functions don't receive a user pointer, so this is how they find it.
+    static ARENA: Cell<*mut Arena> = const { Cell::new(ptr::null_mut()) };
+}
+
+/// Every allocation is preceded by a header holding its size, which also keeps the returned
+/// pointers aligned like `malloc`'s.
+const HEADER: usize = 16;
+
+/// A bump allocator over a borrowed buffer. Freeing or growing the most recent allocation
+/// reuses its memory, anything else is only reclaimed when the arena is dropped.
+struct Arena {
+    base: *mut u8,
+    len: usize,
+    top: usize,
+    last: Option<usize>,
+}
+
+impl Arena {
+    fn new(buffer: &mut [u8]) -> Arena {
+        Arena {
+            base: buffer.as_mut_ptr(),
+            len: buffer.len(),
+            top: 0,
+            last: None,
+        }
+    }
+
+    unsafe fn alloc(&mut self, size: usize) -> *mut u8 {
+        let addr = self.base as usize;
+        let start = match (addr + self.top).checked_add(HEADER - 1) {
+            Some(end) => (end & !(HEADER - 1)) - addr,
+            None => return ptr::null_mut(),
+        };
+        let end = match start
+            .checked_add(HEADER)
+            .and_then(|data| data.checked_add(size))
+        {
+            Some(end) if end <= self.len => end,
+            _ => return ptr::null_mut(),
+        };
+        let header = self.base.add(start);
+        (header as *mut usize).write(size);
+        self.top = end;
+        self.last = Some(start);
+        header.add(HEADER)
+    }
+
+    /// Returns the offset of the header of `data`, an allocation from this arena
+    fn offset(&self, data: *mut u8) -> usize {
+        data as usize - HEADER - self.base as usize
+    }
+
+    unsafe fn realloc(&mut self, data: *mut u8, size: usize) -> *mut u8 {
+        if data.is_null() {
+            return self.alloc(size);
+        }
+        let start = self.offset(data);
+        let header = self.base.add(start) as *mut usize;
+        if self.last == Some(start) {
+            return match (start + HEADER).checked_add(size) {
+                Some(end) if end <= self.len => {
+                    header.write(size);
+                    self.top = end;
+                    data
+                }
+                _ => ptr::null_mut(),
+            };
+        }
+        let new = self.alloc(size);
+        if !new.is_null() {
+            ptr::copy_nonoverlapping(data, new, header.read().min(size));
+        }
+        new
+    }
+
+    fn free(&mut self, data: *mut u8) {
+        if data.is_null() {
+            return;
+        }
+        let start = self.offset(data);
+        if self.last == Some(start) {
+            self.top = start;
+            self.last = None;
+        }
+    }
+}
+
+unsafe fn with_arena(f: impl FnOnce(&mut Arena) -> *mut u8) -> *mut u8 {
+    let arena = ARENA.with(Cell::get);
+    if arena.is_null() {
+        return ptr::null_mut();
+    }
+    f(&mut *arena)
+}
+
+unsafe extern "C" fn malloc_fn(size: usize) -> *mut libc::c_void {
+    with_arena(|arena| arena.alloc(size)) as _
+}
+
+unsafe extern "C" fn realloc_fn(data: *mut libc::c_void, size: usize) -> *mut libc::c_void {
+    with_arena(|arena| arena.realloc(data as _, size)) as _
+}
+
+unsafe extern "C" fn calloc_fn(count: usize, size: usize) -> *mut libc::c_void {
+    let size = match count.checked_mul(size) {
+        Some(size) => size,
+        None => return ptr::null_mut(),
+    };
+    let data = malloc_fn(size);
+    if !data.is_null() {
+        ptr::write_bytes(data as *mut u8, 0, size);
+    }
+    data
+}
+
+unsafe extern "C" fn free_fn(data: *mut libc::c_void) {
+    with_arena(|arena| {
+        arena.free(data as _);
+        ptr::null_mut()
+    });
+}
+
+/// Installs an arena for the current thread and restores the previous one when dropped.
+struct ArenaGuard {
+    previous: *mut Arena,
+}
+
+impl ArenaGuard {
+    fn install(arena: &mut Arena) -> ArenaGuard {
+        let previous = ARENA.with(|current| current.replace(arena));
+        ArenaGuard { previous }
+    }
+}
+
+impl Drop for ArenaGuard {
+    fn drop(&mut self) {
+        ARENA.with(|current| current.set(self.previous));
+    }
+}
+
+/// Decode `png` data from a buffer into `output`, serving every libspng and zlib allocation from
+/// `arena` instead of the heap.
+///
+/// This is meant for real-time and embedded use where allocating during a frame isn't allowed.
+/// Returns `Error::Mem` if the arena is exhausted and `Error::Bufsiz` if `output` is smaller than
+/// the decoded image. The arena needs room for the decoder state, the zlib window (32 KiB) and a
+/// few rows of the image, e.g. 128 KiB covers images up to a few thousand pixels wide.
+///
+/// libspng's allocation functions don't receive a pointer to the context, so the arena is
+/// installed for the current thread for the duration of the call. For the same reason there is
+/// no `Decoder` equivalent, which would have to keep the arena installed between calls.
+pub fn decode_with_arena(
+    data: &[u8],
+    output_format: Format,
+    arena: &mut [u8],
+    output: &mut [u8],
+) -> Result<OutputInfo, Error> {
+    let mut arena = Arena::new(arena);
+    // Dropped after `ctx`, which frees its memory through the arena
+    let _guard = ArenaGuard::install(&mut arena);
+    let alloc = sys::spng_alloc {
+        malloc_fn: Some(malloc_fn),
+        realloc_fn: Some(realloc_fn),
+        calloc_fn: Some(calloc_fn),
+        free_fn: Some(free_fn),
+    };
+    // The allocation functions are plain functions, so they outlive the context
+    let mut ctx = unsafe { RawContext::with_alloc(&alloc, ContextFlags::empty())? };
+    ctx.set_png_buffer(data)?;
+    let output_format = ctx.resolve_format(output_format)?;
+    let ihdr = ctx.get_ihdr()?;
+    let info = Info::from_ihdr(&ihdr)?;
+    if !output_format.supports_decode_from(info.color_type, info.bit_depth) {
+        return Err(Error::Fmt);
+    }
+    let buffer_size = ctx.decoded_image_size(output_format)?;
+    let output = output.get_mut(..buffer_size).ok_or(Error::Bufsiz)?;
+    ctx.decode_image(output, output_format, DecodeFlags::empty())?;
+    OutputInfo::from_ihdr_format_buffer_size(&ihdr, output_format, buffer_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arena_alloc() {
+        let mut buffer = [0u8; 256];
+        let mut arena = Arena::new(&mut buffer);
+        unsafe {
+            let a = arena.alloc(10);
+            assert_eq!(0, a as usize % HEADER);
+            a.write_bytes(1, 10);
+            // Growing the last allocation keeps it in place
+            assert_eq!(a, arena.realloc(a, 20));
+            let b = arena.alloc(8);
+            assert_ne!(a, b);
+            // Growing an earlier allocation copies it
+            let c = arena.realloc(a, 30);
+            assert_ne!(a, c);
+            assert_eq!(&[1; 10], &*ptr::slice_from_raw_parts(c, 10));
+            // Freeing the last allocation reuses its memory
+            arena.free(c);
+            assert_eq!(c, arena.alloc(30));
+            assert!(arena.alloc(256).is_null());
+        }
+        // Nothing is served without an installed arena
+        unsafe { assert!(malloc_fn(1).is_null()) };
+        let _guard = ArenaGuard::install(&mut arena);
+        unsafe { assert!(!malloc_fn(1).is_null()) };
+    }
+}
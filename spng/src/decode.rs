@@ -0,0 +1,968 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+use spng_sys as sys;
+
+use crate::{
+    raw::{
+        chunk::{Text, TextType, Time},
+        ChunkAvail, RawContext,
+    },
+    BitDepth, ChunkType, ColorType, ContextFlags, CrcAction, DecodeFlags, DecodedImage, Error,
+    Format, Info, Limits, OutputInfo, PartialImage, RenderingIntent,
+};
+
+/// PNG decoder
+///
+/// Animated PNGs (APNG) decode to their default image, which is stored in the `IDAT` chunks.
+/// libspng doesn't support APNG and skips the `acTL`, `fcTL`, and `fdAT` chunks like any other
+/// unknown ancillary chunk, so the animation frames are never decoded.
+pub struct Decoder<R> {
+    reader: R,
+    limits: Limits,
+    context_flags: ContextFlags,
+    decode_flags: DecodeFlags,
+    output_format: Format,
+    debug_assert_full_write: bool,
+    composite_over: Option<Background>,
+    lenient: bool,
+}
+
+/// The background an image with alpha is composited over when decoding to `Format::Rgb8`, see
+/// [`Decoder::set_composite_over`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Background {
+    /// The color of the `bKGD` chunk. Images without one are decoded without compositing.
+    Bkgd,
+    /// A `16-bit` RGB color, e.g. `[0xffff; 3]` for white
+    Rgb([u16; 3]),
+}
+
+/// A decoded image with its commonly used metadata, see [`decode_full`]
+#[derive(Debug, Clone)]
+pub struct FullImage {
+    /// The output image information
+    pub info: OutputInfo,
+    /// The decoded pixel data
+    pub data: Vec<u8>,
+    /// The image gamma from `gAMA`
+    pub gamma: Option<f64>,
+    /// The dots per inch as `(x, y)` from `pHYs`, `None` if its unit isn't meters
+    pub dpi: Option<(f64, f64)>,
+    /// The modification time from `tIME`
+    pub time: Option<Time>,
+    /// The keyword and text of every `tEXt`, `zTXt` and `iTXt` chunk
+    pub text: Vec<(String, String)>,
+    /// The rendering intent from `sRGB`
+    pub srgb_intent: Option<RenderingIntent>,
+}
+
+/// PNG reader
+pub struct Reader<R> {
+    ctx: RawContext<R>,
+    ihdr: sys::spng_ihdr,
+    info: Info,
+    output_buffer_size: usize,
+    output_format: Format,
+    decode_flags: DecodeFlags,
+    limits: Limits,
+    context_flags: ContextFlags,
+    debug_assert_full_write: bool,
+    composite_over: Option<Background>,
+    lenient: bool,
+}
+
+impl<R> fmt::Debug for Decoder<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Decoder")
+            .field("limits", &self.limits)
+            .field("context_flags", &self.context_flags)
+            .field("decode_flags", &self.decode_flags)
+            .field("output_format", &self.output_format)
+            .field("debug_assert_full_write", &self.debug_assert_full_write)
+            .field("composite_over", &self.composite_over)
+            .field("lenient", &self.lenient)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R> fmt::Debug for Reader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reader")
+            .field("ctx", &self.ctx)
+            .field("info", &self.info)
+            .field("output_format", &self.output_format)
+            .field("decode_flags", &self.decode_flags)
+            .field("output_buffer_size", &self.output_buffer_size)
+            .finish()
+    }
+}
+
+impl<R> Decoder<R> {
+    /// Create a new `png` decoder with the default limits
+    pub fn new(reader: R) -> Decoder<R> {
+        let decode_flags = DecodeFlags::empty();
+        let context_flags = ContextFlags::empty();
+        let output_format = Format::Png;
+        let limits = Limits::default();
+        Decoder {
+            reader,
+            limits,
+            context_flags,
+            decode_flags,
+            output_format,
+            debug_assert_full_write: false,
+            composite_over: None,
+            lenient: false,
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Decoder<R> {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_context_flags(mut self, context_flags: ContextFlags) -> Decoder<R> {
+        self.context_flags = context_flags;
+        self
+    }
+
+    pub fn with_decode_flags(mut self, decode_flags: DecodeFlags) -> Decoder<R> {
+        self.decode_flags = decode_flags;
+        self
+    }
+
+    pub fn with_output_format(mut self, output_format: Format) -> Decoder<R> {
+        self.output_format = output_format;
+        self
+    }
+
+    pub fn with_debug_assert_full_write(mut self, enabled: bool) -> Decoder<R> {
+        self.debug_assert_full_write = enabled;
+        self
+    }
+
+    pub fn with_composite_over(mut self, background: Option<Background>) -> Decoder<R> {
+        self.composite_over = background;
+        self
+    }
+
+    pub fn with_lenient(mut self, lenient: bool) -> Decoder<R> {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Set the limits
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Set the decoding flags
+    pub fn set_decode_flags(&mut self, decode_flags: DecodeFlags) {
+        self.decode_flags = decode_flags;
+    }
+
+    /// Set the output image format
+    pub fn set_output_format(&mut self, output_format: Format) {
+        self.output_format = output_format;
+    }
+
+    /// Set the context flags. `ContextFlags::ENCODER` is rejected by [`read_info`].
+    ///
+    /// [`read_info`]: method@Decoder::read_info
+    pub fn set_context_flags(&mut self, context_flags: ContextFlags) {
+        self.context_flags = context_flags;
+    }
+
+    /// Debug-asserts that libspng reached the end of the image after [`Reader::next_frame`]
+    /// decodes it, guarding against partially written output buffers.
+    ///
+    /// The check only runs in debug builds.
+    pub fn set_debug_assert_full_write(&mut self, enabled: bool) {
+        self.debug_assert_full_write = enabled;
+    }
+
+    /// Composite images with an alpha channel over `background` when decoding to
+    /// `Format::Rgb8`, instead of discarding the alpha.
+    ///
+    /// PNG alpha is straight, not premultiplied, so each channel is blended as
+    /// `(color * alpha + background * (255 - alpha)) / 255`. The blend is done on the encoded
+    /// samples, without gamma correction. The image is decoded to `Format::Rgba8` first, which
+    /// [`Reader::next_frame`] does in a temporary buffer. Only `next_frame` composites.
+    pub fn set_composite_over(&mut self, background: Option<Background>) {
+        self.composite_over = background;
+    }
+
+    /// Accept images with corrupt checksums, as written by some broken encoders, by combining
+    /// every tolerance libspng offers: CRC mismatches in critical and ancillary chunks are
+    /// ignored with [`CrcAction::Use`] and the `DEFLATE` checksum with
+    /// [`ContextFlags::IGNORE_ADLER32`].
+    ///
+    /// libspng has no option for chunk ordering or unknown critical chunks. Misplaced chunks,
+    /// e.g. `gAMA` after `IDAT`, are handled exactly as without `lenient`, and unknown critical
+    /// chunks are an error either way. The flags from [`set_context_flags`] are kept.
+    ///
+    /// [`set_context_flags`]: method@Decoder::set_context_flags
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Buffers the input as it is read so the image can be decoded again with
+    /// [`Reader::redecode`], e.g. to a second output format.
+    ///
+    /// The whole `png` is held in memory for the lifetime of the `Reader`.
+    pub fn buffered(self) -> Decoder<Replay<R>> {
+        Decoder {
+            reader: Replay {
+                inner: self.reader,
+                buffer: Vec::new(),
+            },
+            limits: self.limits,
+            context_flags: self.context_flags,
+            decode_flags: self.decode_flags,
+            output_format: self.output_format,
+            debug_assert_full_write: self.debug_assert_full_write,
+            composite_over: self.composite_over,
+            lenient: self.lenient,
+        }
+    }
+
+    /// Read the `png` header and initialize decoding.
+    ///
+    /// Returns `Error::CtxType` if the context flags include `ContextFlags::ENCODER`, or
+    /// `Error::LimitsExceeded` if the image exceeds the configured [`Limits`].
+    pub fn read_info(self) -> Result<Reader<R>, Error>
+    where
+        R: io::Read,
+    {
+        if self.context_flags.contains(ContextFlags::ENCODER) {
+            return Err(Error::CtxType);
+        }
+        let mut ctx = if self.lenient {
+            let mut ctx =
+                RawContext::with_flags(self.context_flags | ContextFlags::IGNORE_ADLER32)?;
+            ctx.set_crc_action(CrcAction::Use, CrcAction::Use)?;
+            ctx
+        } else {
+            RawContext::with_flags(self.context_flags)?
+        };
+        ctx.set_png_stream_reader(self.reader)?;
+        ctx.set_max_input_bytes(self.limits.max_input_bytes);
+        let ihdr = ctx.get_ihdr()?;
+        // Check the dimensions first, the buffer size of a huge image overflows
+        self.limits.check(ihdr.width, ihdr.height, None)?;
+        let info = Info::from_ihdr(&ihdr)?;
+        let output_format = ctx.resolve_format(self.output_format)?;
+        if !output_format.supports_decode_from(info.color_type, info.bit_depth) {
+            return Err(Error::Fmt);
+        }
+        let output_buffer_size = ctx.decoded_image_size(output_format)?;
+        self.limits
+            .check(ihdr.width, ihdr.height, Some(output_buffer_size))?;
+        let reader = Reader {
+            ctx,
+            ihdr,
+            info,
+            output_format,
+            decode_flags: self.decode_flags,
+            output_buffer_size,
+            limits: self.limits,
+            context_flags: self.context_flags,
+            debug_assert_full_write: self.debug_assert_full_write,
+            composite_over: self.composite_over,
+            lenient: self.lenient,
+        };
+
+        Ok(reader)
+    }
+}
+
+impl Decoder<BufReader<File>> {
+    /// Opens the file at `path` for decoding, wrapped in a `BufReader`
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Decoder<BufReader<File>>> {
+        let file = File::open(path)?;
+        Ok(Decoder::new(BufReader::new(file)))
+    }
+}
+
+impl<R> Reader<R> {
+    /// Returns input information, parsed once by [`Decoder::read_info`]
+    pub fn info(&self) -> Info {
+        self.info
+    }
+
+    /// Returns `true` if the output format has the same layout as the PNG data, in which case no
+    /// pixel format conversion occurs while decoding.
+    ///
+    /// `Format::Png` and `Format::Raw` are always native. Note that `16-bit` samples are still
+    /// converted to host-endian for every format other than `Format::Raw`, and the `GAMMA` decode
+    /// flag still transforms samples.
+    pub fn is_native_format(&self) -> bool {
+        match self.output_format.layout() {
+            Some(layout) => {
+                let info = self.info();
+                layout == (info.color_type, info.bit_depth)
+            }
+            None => true,
+        }
+    }
+
+    /// Returns the output format used by `next_frame`, never `Format::Auto`
+    pub fn output_format(&self) -> Format {
+        self.output_format
+    }
+
+    /// Returns the decode flags used by `next_frame`
+    pub fn decode_flags(&self) -> DecodeFlags {
+        self.decode_flags
+    }
+
+    /// Returns the minimum buffer size required for `next_frame`
+    #[inline]
+    pub fn output_buffer_size(&self) -> usize {
+        self.output_buffer_size
+    }
+
+    /// Decodes the next frame of the `png`. This currently may only be called once.
+    ///
+    /// Returns `Error::OpState` if the decode flags include `DecodeFlags::PROGRESSIVE`, which
+    /// would only initialize decoding and leave `output` untouched. Use [`rows`],
+    /// [`rows_buffered`] or [`scanlines`] to decode progressively.
+    ///
+    /// [`rows`]: method@Reader::rows
+    /// [`rows_buffered`]: method@Reader::rows_buffered
+    /// [`scanlines`]: method@Reader::scanlines
+    pub fn next_frame(&mut self, output: &mut [u8]) -> Result<OutputInfo, Error> {
+        if self.decode_flags.contains(DecodeFlags::PROGRESSIVE) {
+            return Err(Error::OpState);
+        }
+        if let Some(background) = self.composite_background()? {
+            let output = output
+                .get_mut(..self.output_buffer_size)
+                .ok_or(Error::Bufsiz)?;
+            let mut rgba = vec![0; self.ctx.decoded_image_size(Format::Rgba8)?];
+            self.ctx
+                .decode_image(&mut rgba, Format::Rgba8, self.decode_flags)?;
+            composite_rgba8(&rgba, output, background);
+        } else {
+            self.ctx
+                .decode_image(output, self.output_format, self.decode_flags)?;
+        }
+        if cfg!(debug_assertions) && self.debug_assert_full_write {
+            // Row info is only available while decoding, `Error::Oi` means every row was written
+            debug_assert_eq!(
+                Err(Error::Oi),
+                self.ctx.get_row_info().map(|_| ()),
+                "libspng did not write the whole image"
+            );
+        }
+        let ihdr = self.ctx.get_ihdr()?;
+        let output_info = OutputInfo::from_ihdr_format_buffer_size(
+            &ihdr,
+            self.output_format,
+            self.output_buffer_size(),
+        )?;
+        Ok(output_info)
+    }
+
+    /// Decodes the next frame like [`next_frame`] into `16-bit` host-endian samples.
+    ///
+    /// The output format must be `Format::Rgba16`, `Format::Ga16`, or `Format::Png` for a
+    /// `16-bit` image, otherwise `Error::Fmt` is returned. `output` must hold at least
+    /// [`output_buffer_size`] bytes, i.e. half as many samples.
+    ///
+    /// [`next_frame`]: method@Reader::next_frame
+    /// [`output_buffer_size`]: method@Reader::output_buffer_size
+    pub fn next_frame_u16(&mut self, output: &mut [u16]) -> Result<OutputInfo, Error> {
+        let bit_depth = match self.output_format.layout() {
+            Some((_, bit_depth)) => bit_depth,
+            None if self.output_format == Format::Png => self.info.bit_depth,
+            None => return Err(Error::Fmt),
+        };
+        if bit_depth != BitDepth::Sixteen {
+            return Err(Error::Fmt);
+        }
+        // Any `u16` slice is a valid, aligned `u8` slice of twice the length
+        let output = unsafe {
+            std::slice::from_raw_parts_mut(output.as_mut_ptr() as *mut u8, output.len() * 2)
+        };
+        self.next_frame(output)
+    }
+
+    /// Returns the `16-bit` background to composite over if `next_frame` decodes an image with
+    /// an alpha channel to `Format::Rgb8`.
+    fn composite_background(&self) -> Result<Option<[u16; 3]>, Error> {
+        let alpha = matches!(
+            self.info.color_type,
+            ColorType::GrayscaleAlpha | ColorType::TruecolorAlpha
+        );
+        if self.output_format != Format::Rgb8 || !alpha {
+            return Ok(None);
+        }
+        match self.composite_over {
+            None => Ok(None),
+            Some(Background::Rgb(rgb)) => Ok(Some(rgb)),
+            Some(Background::Bkgd) => {
+                let bkgd = match self.ctx.get_bkgd() {
+                    Ok(bkgd) => bkgd,
+                    Err(Error::Chunkavail) => return Ok(None),
+                    Err(err) => return Err(err),
+                };
+                // `bKGD` samples have the image bit depth, alpha images are 8 or 16-bit
+                let scale = |sample: u16| match self.info.bit_depth {
+                    BitDepth::Eight => (sample & 0xff) * 257,
+                    _ => sample,
+                };
+                Ok(match (bkgd.gray(), bkgd.rgb()) {
+                    (Some(gray), _) => Some([scale(gray); 3]),
+                    (_, Some((red, green, blue))) => Some([scale(red), scale(green), scale(blue)]),
+                    _ => None,
+                })
+            }
+        }
+    }
+
+    /// Decodes the next frame like [`next_frame`], recovering the rows that precede a truncated
+    /// or corrupt `IDAT` stream, and returns the number of rows decoded.
+    ///
+    /// A complete decode returns the image height. If the stream ends early (`Error::IoEof`,
+    /// `Error::Eof`) or the image data is cut short (`Error::IdatTooShort`) the rows decoded so
+    /// far are kept and the rest of `output` is left as-is. Interlaced images only have complete
+    /// rows once the last pass is decoded, so a truncated interlaced image reports `0` rows.
+    ///
+    /// Returns `Error::Bufsiz` if `output` is smaller than [`output_buffer_size`].
+    ///
+    /// [`next_frame`]: method@Reader::next_frame
+    /// [`output_buffer_size`]: method@Reader::output_buffer_size
+    pub fn next_frame_partial(&mut self, output: &mut [u8]) -> Result<(OutputInfo, u32), Error> {
+        let buffer_size = self.output_buffer_size;
+        if output.len() < buffer_size {
+            return Err(Error::Bufsiz);
+        }
+        let height = self.ihdr.height;
+        let interlaced = self.ihdr.interlace_method != 0;
+        let line_size = buffer_size / height as usize;
+        let decode_flags = self.decode_flags | DecodeFlags::PROGRESSIVE;
+        self.ctx
+            .decode_image(&mut [], self.output_format, decode_flags)?;
+        let mut rows_decoded = 0;
+        loop {
+            let row_info = match self.ctx.get_row_info() {
+                Ok(row_info) => row_info,
+                Err(Error::Oi) => {
+                    rows_decoded = height;
+                    break;
+                }
+                Err(Error::IoEof | Error::Eof | Error::IdatTooShort) => break,
+                Err(error) => return Err(error),
+            };
+            let start = row_info.row_num as usize * line_size;
+            match self.ctx.decode_row(&mut output[start..start + line_size]) {
+                Ok(()) if !interlaced => rows_decoded = row_info.row_num + 1,
+                Ok(()) => {}
+                Err(Error::Oi) => {
+                    rows_decoded = height;
+                    break;
+                }
+                Err(Error::IoEof | Error::Eof | Error::IdatTooShort) => break,
+                Err(error) => return Err(error),
+            }
+        }
+        let info =
+            OutputInfo::from_ihdr_format_buffer_size(&self.ihdr, self.output_format, buffer_size)?;
+        Ok((info, rows_decoded))
+    }
+
+    /// Decodes the image to `output_format` with `decode_flags`, ignoring the format and flags
+    /// the `Decoder` was configured with.
+    ///
+    /// Returns `Error::Bufsiz` if `output` is smaller than the decoded image size for
+    /// `output_format`. libspng allows only one decode per context, so this consumes the decode
+    /// and neither `next_frame` nor `decode_as` may be called afterwards.
+    ///
+    /// `Format::Auto` is resolved for the image like [`Decoder::read_info`] does, and
+    /// `Error::Fmt` is returned if the image can't be decoded to `output_format`. Like
+    /// [`next_frame`], `Error::OpState` is returned if `decode_flags` include
+    /// `DecodeFlags::PROGRESSIVE`.
+    ///
+    /// [`next_frame`]: method@Reader::next_frame
+    pub fn decode_as(
+        &mut self,
+        output: &mut [u8],
+        output_format: Format,
+        decode_flags: DecodeFlags,
+    ) -> Result<OutputInfo, Error> {
+        if decode_flags.contains(DecodeFlags::PROGRESSIVE) {
+            return Err(Error::OpState);
+        }
+        let output_format = self.ctx.resolve_format(output_format)?;
+        if !output_format.supports_decode_from(self.info.color_type, self.info.bit_depth) {
+            return Err(Error::Fmt);
+        }
+        let buffer_size = self.ctx.decoded_image_size(output_format)?;
+        if output.len() < buffer_size {
+            return Err(Error::Bufsiz);
+        }
+        self.ctx.decode_image(output, output_format, decode_flags)?;
+        OutputInfo::from_ihdr_format_buffer_size(&self.ihdr, output_format, buffer_size)
+    }
+
+    /// Returns `true` if the image has a chunk of type `chunk`, see [`RawContext::has_chunk`].
+    ///
+    /// Errors reading the chunks are reported as `false`, the same error is returned by the next
+    /// decode.
+    pub fn has_chunk(&self, chunk: ChunkType) -> bool {
+        self.ctx.has_chunk(chunk).unwrap_or(false)
+    }
+
+    /// Decodes the image into separately allocated rows, e.g. for tiled consumers that can't
+    /// provide a contiguous buffer.
+    ///
+    /// `rows` must have one slice per image row, each at least `output_buffer_size / height`
+    /// bytes long, otherwise `Error::Bufsiz` is returned. Interlaced images are supported, every
+    /// row is written to in each pass that covers it.
+    ///
+    /// This consumes the decode, `next_frame` may not be called afterwards.
+    pub fn decode_rows_scatter(&mut self, rows: &mut [&mut [u8]]) -> Result<OutputInfo, Error> {
+        let height = self.ihdr.height;
+        let line_size = self.output_buffer_size / height as usize;
+        if rows.len() != height as usize || rows.iter().any(|row| row.len() < line_size) {
+            return Err(Error::Bufsiz);
+        }
+        let decode_flags = self.decode_flags | DecodeFlags::PROGRESSIVE;
+        self.ctx
+            .decode_image(&mut [], self.output_format, decode_flags)?;
+        loop {
+            let row_info = self.ctx.get_row_info()?;
+            let row = &mut rows[row_info.row_num as usize][..line_size];
+            match self.ctx.decode_row(row) {
+                Ok(()) => continue,
+                Err(Error::Oi) => break,
+                Err(error) => return Err(error),
+            }
+        }
+        OutputInfo::from_ihdr_format_buffer_size(
+            &self.ihdr,
+            self.output_format,
+            self.output_buffer_size,
+        )
+    }
+
+    /// Returns a reference to the `RawContext`.
+    pub fn raw_context(&self) -> &RawContext<R> {
+        &self.ctx
+    }
+
+    /// Decodes the image one row at a time.
+    ///
+    /// The returned [`Rows`] allocates a single scanline buffer and reuses it for every row.
+    /// Interlaced images must be fully decoded before any row is complete, so for those the whole
+    /// image is buffered on the first call to [`Rows::next_row`].
+    ///
+    /// This consumes the decode, `next_frame` may not be called afterwards.
+    pub fn rows(&mut self) -> Result<Rows<'_, R>, Error> {
+        let height = self.ihdr.height;
+        let line_size = self.output_buffer_size / height as usize;
+        let interlaced = self.ihdr.interlace_method != 0;
+        let decode_flags = self.decode_flags | DecodeFlags::PROGRESSIVE;
+        self.ctx
+            .decode_image(&mut [], self.output_format, decode_flags)?;
+        let buffer_size = if interlaced {
+            self.output_buffer_size
+        } else {
+            line_size
+        };
+        Ok(Rows {
+            ctx: &mut self.ctx,
+            buffer: vec![0; buffer_size],
+            line_size,
+            height,
+            interlaced,
+            row: 0,
+        })
+    }
+
+    /// Returns the underlying reader, positioned after the last chunk read by the decoder.
+    pub fn into_inner(self) -> R {
+        self.ctx
+            .into_stream()
+            .expect("the stream is set by Decoder::read_info")
+    }
+
+    /// Decodes a non-interlaced image one scanline at a time.
+    ///
+    /// This is the cheapest per-row path as it skips the deinterlacing bookkeeping of [`rows`].
+    /// Scanlines are yielded in the order they are stored in the PNG, which for interlaced images
+    /// is the raw Adam7 pass order rather than the final row order. Interlaced images therefore
+    /// return `Error::InterlaceMethod`, use [`rows`] instead.
+    ///
+    /// This consumes the decode, `next_frame` may not be called afterwards.
+    ///
+    /// [`rows`]: method@Reader::rows
+    pub fn scanlines(&mut self) -> Result<Scanlines<'_, R>, Error> {
+        if self.ihdr.interlace_method != 0 {
+            return Err(Error::InterlaceMethod);
+        }
+        let height = self.ihdr.height;
+        let line_size = self.output_buffer_size / height as usize;
+        let decode_flags = self.decode_flags | DecodeFlags::PROGRESSIVE;
+        self.ctx
+            .decode_image(&mut [], self.output_format, decode_flags)?;
+        Ok(Scanlines {
+            ctx: &mut self.ctx,
+            buffer: vec![0; line_size],
+            height,
+            row: 0,
+        })
+    }
+
+    /// Decodes the image one row at a time, yielding an owned `Vec` per row.
+    ///
+    /// Prefer [`rows`] unless the rows need to be retained.
+    ///
+    /// [`rows`]: method@Reader::rows
+    pub fn rows_buffered(&mut self) -> Result<RowsBuffered<'_, R>, Error> {
+        Ok(RowsBuffered { rows: self.rows()? })
+    }
+
+    /// Reads past the image data to the end-of-file (IEND) marker so that chunks stored after
+    /// the image data, e.g. `tIME` or `tEXt`, are available from [`raw_context`].
+    ///
+    /// libspng can't skip the image data, so it is decoded one row at a time into a single
+    /// scanline buffer and discarded. This consumes the decode, the pixels can't be decoded
+    /// afterwards.
+    ///
+    /// [`raw_context`]: method@Reader::raw_context
+    pub fn read_trailing_chunks(&mut self) -> Result<(), Error> {
+        self.ctx.validate()
+    }
+}
+
+impl<R: io::BufRead> Reader<R> {
+    /// Starts decoding the next image of a stream of concatenated PNGs, using the same
+    /// configuration as the `Decoder` that created this `Reader`.
+    ///
+    /// The image data must have been decoded before calling this. The remaining chunks up to and
+    /// including `IEND` are read, then `Ok(None)` is returned if the stream has no more data.
+    /// Otherwise the next image is read exactly like [`Decoder::read_info`], so a truncated or
+    /// corrupt image is returned as an error rather than `None`.
+    pub fn next_image(mut self) -> Result<Option<Reader<R>>, Error> {
+        self.ctx.decode_chunks()?;
+        let (limits, context_flags) = (self.limits, self.context_flags);
+        let (output_format, decode_flags) = (self.output_format, self.decode_flags);
+        let debug_assert_full_write = self.debug_assert_full_write;
+        let (composite_over, lenient) = (self.composite_over, self.lenient);
+        let mut reader = self.into_inner();
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        Decoder::new(reader)
+            .with_limits(limits)
+            .with_context_flags(context_flags)
+            .with_output_format(output_format)
+            .with_decode_flags(decode_flags)
+            .with_debug_assert_full_write(debug_assert_full_write)
+            .with_composite_over(composite_over)
+            .with_lenient(lenient)
+            .read_info()
+            .map(Some)
+    }
+}
+
+impl<R: io::Read> Reader<Replay<R>> {
+    /// Decodes the image again to `output_format` from the input buffered by
+    /// [`Decoder::buffered`], using the limits, context flags and decode flags of the `Decoder`.
+    /// The whole image is decoded even if the decode flags include `DecodeFlags::PROGRESSIVE`.
+    ///
+    /// The rest of the input is read into the buffer first, so the image can't be decoded from
+    /// the stream with [`next_frame`] afterwards unless it already was.
+    ///
+    /// [`next_frame`]: method@Reader::next_frame
+    pub fn redecode(&mut self, output_format: Format) -> Result<(OutputInfo, Vec<u8>), Error> {
+        let replay = self
+            .ctx
+            .stream_mut()
+            .expect("the stream is set by Decoder::read_info");
+        io::copy(replay, &mut io::sink())?;
+        let mut reader = Decoder::new(replay.buffer.as_slice())
+            .with_limits(self.limits)
+            .with_context_flags(self.context_flags)
+            .with_decode_flags(self.decode_flags - DecodeFlags::PROGRESSIVE)
+            .with_output_format(output_format)
+            .with_debug_assert_full_write(self.debug_assert_full_write)
+            .with_composite_over(self.composite_over)
+            .with_lenient(self.lenient)
+            .read_info()?;
+        let mut out = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut out)?;
+        Ok((info, out))
+    }
+}
+
+/// Input adapter returned by [`Decoder::buffered`] that keeps a copy of everything read
+pub struct Replay<R> {
+    inner: R,
+    buffer: Vec<u8>,
+}
+
+impl<R> Replay<R> {
+    /// Returns the input read so far
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl<R: io::Read> io::Read for Replay<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.buffer.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Row-by-row decoder returned by [`Reader::rows`]
+pub struct Rows<'a, R> {
+    ctx: &'a mut RawContext<R>,
+    buffer: Vec<u8>,
+    line_size: usize,
+    height: u32,
+    interlaced: bool,
+    row: u32,
+}
+
+impl<'a, R> Rows<'a, R> {
+    /// Decodes the next row in final image order.
+    ///
+    /// The returned slice borrows the shared scanline buffer and is invalidated by the next call.
+    /// Returns `None` after the last row.
+    pub fn next_row(&mut self) -> Option<Result<&[u8], Error>> {
+        if self.row >= self.height {
+            return None;
+        }
+        let row = self.row as usize;
+        self.row += 1;
+        if self.interlaced {
+            if row == 0 {
+                if let Err(error) = self.decode_interlaced() {
+                    self.row = self.height;
+                    return Some(Err(error));
+                }
+            }
+            let start = row * self.line_size;
+            return Some(Ok(&self.buffer[start..start + self.line_size]));
+        }
+        match self.ctx.decode_row(&mut self.buffer) {
+            Ok(()) | Err(Error::Oi) => Some(Ok(&self.buffer)),
+            Err(error) => {
+                self.row = self.height;
+                Some(Err(error))
+            }
+        }
+    }
+
+    /// Returns the number of rows returned by [`next_row`] and the image height, e.g. for a
+    /// progress indicator. Both are equal once decoding finished or failed.
+    ///
+    /// [`next_row`]: method@Rows::next_row
+    pub fn progress(&self) -> (u32, u32) {
+        (self.row, self.height)
+    }
+
+    fn decode_interlaced(&mut self) -> Result<(), Error> {
+        loop {
+            let row_info = self.ctx.get_row_info()?;
+            let start = row_info.row_num as usize * self.line_size;
+            let out = &mut self.buffer[start..start + self.line_size];
+            match self.ctx.decode_row(out) {
+                Ok(()) => continue,
+                Err(Error::Oi) => return Ok(()),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Scanline decoder returned by [`Reader::scanlines`]
+pub struct Scanlines<'a, R> {
+    ctx: &'a mut RawContext<R>,
+    buffer: Vec<u8>,
+    height: u32,
+    row: u32,
+}
+
+impl<'a, R> Scanlines<'a, R> {
+    /// Decodes the next scanline.
+    ///
+    /// The returned slice borrows the shared scanline buffer and is invalidated by the next call.
+    /// Returns `None` after the last scanline.
+    pub fn next_scanline(&mut self) -> Option<Result<&[u8], Error>> {
+        if self.row >= self.height {
+            return None;
+        }
+        self.row += 1;
+        match self.ctx.decode_scanline(&mut self.buffer) {
+            Ok(()) | Err(Error::Oi) => Some(Ok(&self.buffer)),
+            Err(error) => {
+                self.row = self.height;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Row-by-row iterator returned by [`Reader::rows_buffered`]
+pub struct RowsBuffered<'a, R> {
+    rows: Rows<'a, R>,
+}
+
+impl<'a, R> Iterator for RowsBuffered<'a, R> {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next_row().map(|row| row.map(<[u8]>::to_vec))
+    }
+}
+
+/// Blends straight alpha `Rgba8` pixels over a `16-bit` `background` into `Rgb8` pixels
+fn composite_rgba8(rgba: &[u8], rgb: &mut [u8], background: [u16; 3]) {
+    let background = background.map(|sample| (sample as u32 * 255 + 0x7fff) / 0xffff);
+    for (src, dst) in rgba.chunks_exact(4).zip(rgb.chunks_exact_mut(3)) {
+        let alpha = src[3] as u32;
+        for ((dst, &src), background) in dst.iter_mut().zip(src).zip(background) {
+            *dst = ((src as u32 * alpha + background * (255 - alpha) + 127) / 255) as u8;
+        }
+    }
+}
+
+/// Decode `png` data.
+///
+/// The output is always a new buffer, see [`decode_bytes`](crate::decode_bytes).
+pub fn decode<R>(reader: R, output_format: Format) -> Result<(OutputInfo, Vec<u8>), Error>
+where
+    R: io::Read,
+{
+    let decoder = Decoder::new(reader).with_output_format(output_format);
+    let mut reader = decoder.read_info()?;
+    let mut out = Vec::new();
+    out.reserve_exact(reader.output_buffer_size());
+    unsafe {
+        out.set_len(reader.output_buffer_size());
+    }
+    let out_info = reader.next_frame(&mut out)?;
+    Ok((out_info, out))
+}
+
+/// Decode the `png` file at `path`.
+pub fn decode_path<P>(path: P, output_format: Format) -> Result<(OutputInfo, Vec<u8>), Error>
+where
+    P: AsRef<Path>,
+{
+    let file = BufReader::new(File::open(path)?);
+    decode(file, output_format)
+}
+
+/// Decode `png` data along with its gamma, pixel dimensions, modification time, text and sRGB
+/// rendering intent.
+///
+/// The chunks after the image data are read as well, so metadata stored at the end of the file,
+/// e.g. `tIME`, is included.
+pub fn decode_full<R>(reader: R, output_format: Format) -> Result<FullImage, Error>
+where
+    R: io::Read,
+{
+    let decoder = Decoder::new(reader).with_output_format(output_format);
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    let ctx = &mut reader.ctx;
+    ctx.decode_chunks()?;
+    let text = match ctx.get_text().chunk_avail()? {
+        Some(text) => text.iter().map(text_entry).collect(),
+        None => Vec::new(),
+    };
+    Ok(FullImage {
+        info,
+        data,
+        gamma: ctx.get_gama().chunk_avail()?,
+        dpi: ctx.get_phys().chunk_avail()?.and_then(|phys| phys.dpi()),
+        time: ctx.get_time().chunk_avail()?,
+        text,
+        srgb_intent: ctx.get_srgb_intent().chunk_avail()?,
+    })
+}
+
+/// Copies a text chunk, `tEXt` and `zTXt` are Latin-1 while `iTXt` is UTF-8
+fn text_entry(text: &Text) -> (String, String) {
+    let latin1 = |bytes: &[u8]| bytes.iter().map(|&byte| byte as char).collect::<String>();
+    let value = match text.text_type() {
+        Ok(TextType::Itxt) => String::from_utf8_lossy(text.text_bytes()).into_owned(),
+        _ => latin1(text.text_bytes()),
+    };
+    (latin1(text.keyword_bytes()), value)
+}
+
+/// Reads the first 8 bytes of `reader` and returns `true` if they are the `png` signature.
+///
+/// Returns `Ok(false)` if the reader ends before 8 bytes. The bytes are consumed, use
+/// [`is_png`](crate::is_png) to check data that is already in memory.
+pub fn detect<R>(mut reader: R) -> io::Result<bool>
+where
+    R: io::Read,
+{
+    let mut signature = [0; 8];
+    match reader.read_exact(&mut signature) {
+        Ok(()) => Ok(crate::is_png(&signature)),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// Decode `png` data, recovering the rows that precede a truncated or corrupt `IDAT` stream.
+///
+/// Rows are decoded progressively. If the stream ends early (`Error::IoEof`, `Error::Eof`) or
+/// the image data is cut short (`Error::IdatTooShort`), the rows decoded so far are returned
+/// and the remaining rows are left zeroed. Any other error is returned as-is, as are errors
+/// reading the header.
+///
+/// Interlaced images only have complete rows once the last pass is decoded, so a truncated
+/// interlaced image reports `0` decoded rows even though earlier passes are present in the data.
+pub fn decode_partial<R>(reader: R, output_format: Format) -> Result<PartialImage, Error>
+where
+    R: io::Read,
+{
+    let decoder = Decoder::new(reader).with_output_format(output_format);
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let (info, rows_decoded) = reader.next_frame_partial(&mut data)?;
+    Ok(PartialImage {
+        image: DecodedImage::new(info, data),
+        rows_decoded,
+    })
+}
+
+/// Decode `png` data and return the average color as `RGBA8`.
+///
+/// libspng does not support decoding a downscaled image, so this decodes the full image to
+/// [`Format::Rgba8`] before averaging. The cost is the same as [`decode`].
+pub fn sample_average_color<R>(reader: R) -> Result<[u8; 4], Error>
+where
+    R: io::Read,
+{
+    let (_, out) = decode(reader, Format::Rgba8)?;
+    let pixels = (out.len() / 4).max(1) as u64;
+    let mut sum = [0u64; 4];
+    for pixel in out.chunks_exact(4) {
+        for (total, &sample) in sum.iter_mut().zip(pixel) {
+            *total += sample as u64;
+        }
+    }
+    let mut average = [0u8; 4];
+    for (avg, total) in average.iter_mut().zip(&sum) {
+        *avg = ((total + pixels / 2) / pixels) as u8;
+    }
+    Ok(average)
+}
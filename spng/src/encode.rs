@@ -0,0 +1,571 @@
+use std::{
+    convert::TryFrom,
+    fmt,
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use spng_sys as sys;
+
+use crate::{
+    raw::{
+        chunk::{Bkgd, Ihdr, Phys, Plte, Time, Trns},
+        RawContext,
+    },
+    BitDepth, ColorType, ContextFlags, Decoder, EncodeFlags, Error, FilterChoice, Format, Info,
+    InterlaceMethod, Limits, OutputInfo, SpngOption,
+};
+
+/// PNG encoder
+pub struct Encoder<W> {
+    writer: W,
+    ihdr: Ihdr,
+    chunks: EncodeChunks,
+    limits: Limits,
+    context_flags: ContextFlags,
+    compression_level: Option<i32>,
+    filter_choice: Option<FilterChoice>,
+    source_format: Format,
+}
+
+/// Ancillary chunks written by [`Encoder::write_info`]
+///
+/// libspng rejects some chunks unless others were set first, e.g. the `tRNS` and `bKGD` chunks
+/// of an indexed image require the `PLTE` chunk. The chunks are set in the order required by
+/// the PNG specification regardless of the order of the builder calls.
+///
+/// `sRGB`, `iCCP` and `cHRM` can't be written yet, [`RawContext`] has no setters for them.
+#[derive(Debug, Clone, Default)]
+pub struct EncodeChunks {
+    gama: Option<f64>,
+    plte: Option<Plte>,
+    trns: Option<Trns>,
+    bkgd: Option<Bkgd>,
+    phys: Option<Phys>,
+    time: Option<Time>,
+    text: Vec<(String, String)>,
+}
+
+/// PNG writer
+///
+/// Call [`finish`] after writing the image data to flush the output and surface any errors.
+/// Dropping a `Writer` without calling [`finish`] flushes the output on a best-effort basis and
+/// ignores any errors.
+///
+/// [`finish`]: method@Writer::finish
+pub struct Writer<W: io::Write> {
+    ctx: Option<RawContext<W>>,
+    ihdr: Ihdr,
+    format: Format,
+    state: WriterState,
+    /// The rows of an interlaced image, encoded once the last row was written
+    interlaced_rows: Vec<u8>,
+}
+
+/// Progress of the image data written by a [`Writer`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum WriterState {
+    /// No image data has been written
+    Ready,
+    /// Rows are being written progressively
+    Rows { written: u32 },
+    /// The image data and the end-of-file (IEND) marker have been written
+    Finalized,
+}
+
+impl<W> fmt::Debug for Encoder<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Encoder")
+            .field("ihdr", &self.ihdr)
+            .field("chunks", &self.chunks)
+            .field("limits", &self.limits)
+            .field("context_flags", &self.context_flags)
+            .field("compression_level", &self.compression_level)
+            .field("filter_choice", &self.filter_choice)
+            .field("source_format", &self.source_format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W: io::Write> fmt::Debug for Writer<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Writer")
+            .field("ctx", &self.ctx)
+            .field("ihdr", &self.ihdr)
+            .field("format", &self.format)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl<W> Encoder<W> {
+    /// Create a new `png` encoder for an `8-bit` RGBA image
+    ///
+    /// libspng writes the output in many small pieces, so an unbuffered `writer` such as a `File`
+    /// should be wrapped in a `BufWriter`. See [`Encoder::create`] for files.
+    pub fn new(writer: W, width: u32, height: u32) -> Encoder<W> {
+        let ihdr = sys::spng_ihdr {
+            width,
+            height,
+            bit_depth: BitDepth::Eight as u8,
+            color_type: ColorType::TruecolorAlpha as u8,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let limits = Limits::default();
+        let context_flags = ContextFlags::ENCODER;
+        Encoder {
+            writer,
+            ihdr,
+            chunks: EncodeChunks::default(),
+            limits,
+            context_flags,
+            compression_level: None,
+            filter_choice: None,
+            source_format: Format::Png,
+        }
+    }
+
+    pub fn with_color_type(mut self, color_type: ColorType) -> Encoder<W> {
+        self.ihdr.color_type = color_type as u8;
+        self
+    }
+
+    pub fn with_bit_depth(mut self, bit_depth: BitDepth) -> Encoder<W> {
+        self.ihdr.bit_depth = bit_depth as u8;
+        self
+    }
+
+    /// Set the dimensions, color type, and bit depth from decoded output, e.g. to re-encode a
+    /// [`DecodedImage`](crate::DecodedImage). The image is written without interlacing.
+    pub fn with_info(mut self, output_info: &OutputInfo) -> Encoder<W> {
+        self.ihdr = Ihdr::from(output_info);
+        self
+    }
+
+    /// Set the dimensions, color type, bit depth, and interlace method from the [`Info`] of a
+    /// decoded `png`.
+    pub fn with_info_from(mut self, info: &Info) -> Encoder<W> {
+        self.ihdr = Ihdr::from(info);
+        self
+    }
+
+    pub fn with_interlace(mut self, interlace_method: InterlaceMethod) -> Encoder<W> {
+        self.ihdr.interlace_method = interlace_method as u8;
+        self
+    }
+
+    pub fn with_chunks(mut self, chunks: EncodeChunks) -> Encoder<W> {
+        self.chunks = chunks;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Encoder<W> {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_context_flags(mut self, context_flags: ContextFlags) -> Encoder<W> {
+        self.context_flags = context_flags;
+        self
+    }
+
+    pub fn with_compression_level(mut self, compression_level: i32) -> Encoder<W> {
+        self.compression_level = Some(compression_level);
+        self
+    }
+
+    pub fn with_filter_choice(mut self, filter_choice: FilterChoice) -> Encoder<W> {
+        self.filter_choice = Some(filter_choice);
+        self
+    }
+
+    pub fn with_source_format(mut self, source_format: Format) -> Encoder<W> {
+        self.source_format = source_format;
+        self
+    }
+
+    /// Set the color type
+    pub fn set_color_type(&mut self, color_type: ColorType) {
+        self.ihdr.color_type = color_type as u8;
+    }
+
+    /// Set the per-component bit depth
+    pub fn set_bit_depth(&mut self, bit_depth: BitDepth) {
+        self.ihdr.bit_depth = bit_depth as u8;
+    }
+
+    /// Set the interlace method. The image data is always written in row order and interlaced
+    /// by the encoder. With [`Writer::write_row`] the rows of an interlaced image are buffered
+    /// until the last row was written.
+    pub fn set_interlace(&mut self, interlace_method: InterlaceMethod) {
+        self.ihdr.interlace_method = interlace_method as u8;
+    }
+
+    /// Set the ancillary chunks
+    pub fn set_chunks(&mut self, chunks: EncodeChunks) {
+        self.chunks = chunks;
+    }
+
+    /// Set the image limits. The image dimensions are checked against these in [`write_info`].
+    ///
+    /// [`write_info`]: method@Encoder::write_info
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Set the context flags. `ContextFlags::ENCODER` is always added by [`write_info`].
+    ///
+    /// [`write_info`]: method@Encoder::write_info
+    pub fn set_context_flags(&mut self, context_flags: ContextFlags) {
+        self.context_flags = context_flags;
+    }
+
+    /// Set the format of the image data passed to the [`Writer`], `Format::Png` by default.
+    ///
+    /// `Format::Png` is the PNG's format with `16-bit` samples in host-endian, as decoded with
+    /// `Format::Png`. `Format::Raw` is the PNG's format with `16-bit` samples in big-endian. Any
+    /// other format, e.g. host-endian `Format::Rgba16`, must have the layout of the image's color
+    /// type and bit depth, otherwise [`write_info`] returns `Error::Fmt`. libspng doesn't convert
+    /// between formats when encoding.
+    ///
+    /// [`write_info`]: method@Encoder::write_info
+    pub fn set_source_format(&mut self, source_format: Format) {
+        self.source_format = source_format;
+    }
+
+    /// Set the zlib compression level of the image data, from `0` (none) to `9` (best). libspng
+    /// uses level `6` by default.
+    pub fn set_compression_level(&mut self, compression_level: i32) {
+        self.compression_level = Some(compression_level);
+    }
+
+    /// Set the scanline filters the encoder may choose from. libspng chooses from all filters by
+    /// default.
+    pub fn set_filter_choice(&mut self, filter_choice: FilterChoice) {
+        self.filter_choice = Some(filter_choice);
+    }
+
+    /// Write the `png` header and initialize encoding.
+    ///
+    /// Returns `Error::LimitsExceeded` if the image dimensions exceed the configured [`Limits`],
+    /// before anything is written.
+    pub fn write_info(self) -> Result<Writer<W>, Error>
+    where
+        W: io::Write,
+    {
+        self.limits.check(self.ihdr.width, self.ihdr.height, None)?;
+        let format = match self.source_format.layout() {
+            Some(layout) if layout == self.layout()? => Format::Png,
+            Some(_) => return Err(Error::Fmt),
+            None if self.source_format == Format::Auto => return Err(Error::Fmt),
+            None => self.source_format,
+        };
+        let mut ctx = RawContext::with_flags(self.context_flags | ContextFlags::ENCODER)?;
+        ctx.set_png_stream_writer(self.writer)?;
+        ctx.set_ihdr(&self.ihdr)?;
+        if let Some(compression_level) = self.compression_level {
+            ctx.set_option(SpngOption::ZlibCompressionLevel, compression_level)?;
+        }
+        if let Some(filter_choice) = self.filter_choice {
+            ctx.set_option(SpngOption::FilterChoice, filter_choice.bits() as i32)?;
+        }
+        self.chunks.apply(&mut ctx)?;
+        Ok(Writer {
+            ctx: Some(ctx),
+            ihdr: self.ihdr,
+            format,
+            state: WriterState::Ready,
+            interlaced_rows: Vec::new(),
+        })
+    }
+}
+
+impl<W> Encoder<W> {
+    fn layout(&self) -> Result<(ColorType, BitDepth), Error> {
+        let color_type = ColorType::try_from(self.ihdr.color_type)?;
+        let bit_depth = BitDepth::try_from(self.ihdr.bit_depth)?;
+        Ok((color_type, bit_depth))
+    }
+}
+
+impl Encoder<Vec<u8>> {
+    /// Reserves capacity for at least `additional` more bytes of output, avoiding reallocations
+    /// while encoding when the size of the `png` can be estimated, e.g. from a previous encode.
+    ///
+    /// libspng has no size hint for its own output buffer, the output is written to the `Vec`
+    /// as it's encoded.
+    pub fn reserve(&mut self, additional: usize) {
+        self.writer.reserve(additional);
+    }
+}
+
+impl Encoder<BufWriter<File>> {
+    /// Creates the file at `path` for encoding, wrapped in a `BufWriter`. The `BufWriter` is
+    /// flushed by [`Writer::finish`].
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        width: u32,
+        height: u32,
+    ) -> io::Result<Encoder<BufWriter<File>>> {
+        let file = File::create(path)?;
+        Ok(Encoder::new(BufWriter::new(file), width, height))
+    }
+}
+
+impl EncodeChunks {
+    pub fn new() -> EncodeChunks {
+        EncodeChunks::default()
+    }
+
+    /// Set the image gamma (`gAMA`)
+    pub fn with_gama(mut self, gamma: f64) -> EncodeChunks {
+        self.gama = Some(gamma);
+        self
+    }
+
+    /// Set the palette (`PLTE`)
+    pub fn with_plte(mut self, plte: Plte) -> EncodeChunks {
+        self.plte = Some(plte);
+        self
+    }
+
+    /// Set the transparency (`tRNS`)
+    pub fn with_trns(mut self, trns: Trns) -> EncodeChunks {
+        self.trns = Some(trns);
+        self
+    }
+
+    /// Set the background color (`bKGD`)
+    pub fn with_bkgd(mut self, bkgd: Bkgd) -> EncodeChunks {
+        self.bkgd = Some(bkgd);
+        self
+    }
+
+    /// Set the physical pixel dimensions (`pHYs`)
+    pub fn with_phys(mut self, phys: Phys) -> EncodeChunks {
+        self.phys = Some(phys);
+        self
+    }
+
+    /// Set the modification time (`tIME`)
+    pub fn with_time(mut self, time: Time) -> EncodeChunks {
+        self.time = Some(time);
+        self
+    }
+
+    /// Add a `tEXt` chunk, see [`RawContext::set_text`]
+    pub fn with_text(mut self, keyword: &str, text: &str) -> EncodeChunks {
+        self.text.push((keyword.to_owned(), text.to_owned()));
+        self
+    }
+
+    fn apply<W: io::Write>(&self, ctx: &mut RawContext<W>) -> Result<(), Error> {
+        if let Some(gamma) = self.gama {
+            ctx.set_gama(gamma)?;
+        }
+        if let Some(plte) = &self.plte {
+            ctx.set_plte(plte)?;
+        }
+        if let Some(trns) = &self.trns {
+            ctx.set_trns(trns)?;
+        }
+        if let Some(bkgd) = &self.bkgd {
+            ctx.set_bkgd(bkgd)?;
+        }
+        if let Some(phys) = &self.phys {
+            ctx.set_phys(phys)?;
+        }
+        if let Some(time) = &self.time {
+            ctx.set_time(time)?;
+        }
+        if !self.text.is_empty() {
+            let text: Vec<(&str, &str)> = self
+                .text
+                .iter()
+                .map(|(keyword, text)| (keyword.as_str(), text.as_str()))
+                .collect();
+            ctx.set_text(&text)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: io::Write> Writer<W> {
+    /// Encodes the image and writes the end-of-file (IEND) marker.
+    ///
+    /// The `data` must be in the source format set with [`Encoder::set_source_format`]. This may
+    /// only be called once.
+    pub fn write_image_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        let ctx = self.ctx.as_mut().expect("writer already finished");
+        ctx.encode_image(data, self.format, EncodeFlags::FINALIZE)?;
+        self.state = WriterState::Finalized;
+        Ok(())
+    }
+
+    /// Encodes the next row of the image, interlacing it if needed. The end-of-file (IEND)
+    /// marker is written after the last row.
+    ///
+    /// The `row` must be in the source format set with [`Encoder::set_source_format`]. This can't
+    /// be combined with [`write_image_data`]. Returns `Error::Oi` if every row was already written.
+    ///
+    /// Rows are always written in image order. The Adam7 passes of an interlaced image need rows
+    /// from the whole image, so those rows are buffered and encoded once the last row was written.
+    ///
+    /// [`write_image_data`]: method@Writer::write_image_data
+    pub fn write_row(&mut self, row: &[u8]) -> Result<(), Error> {
+        let ctx = self.ctx.as_mut().expect("writer already finished");
+        let interlaced = self.ihdr.interlace_method != InterlaceMethod::None as u8;
+        let written = match self.state {
+            WriterState::Ready if interlaced => 0,
+            WriterState::Ready => {
+                let flags = EncodeFlags::PROGRESSIVE | EncodeFlags::FINALIZE;
+                ctx.encode_image(&[], self.format, flags)?;
+                0
+            }
+            WriterState::Rows { written } => written,
+            WriterState::Finalized => return Err(Error::Oi),
+        };
+        let written = if interlaced {
+            let color_type = ColorType::try_from(self.ihdr.color_type)?;
+            let line_bits =
+                self.ihdr.width as usize * color_type.samples() * self.ihdr.bit_depth as usize;
+            let line_size = line_bits.div_ceil(8);
+            let row = row.get(..line_size).ok_or(Error::Bufsiz)?;
+            self.interlaced_rows.extend_from_slice(row);
+            let written = written + 1;
+            if written >= self.ihdr.height {
+                let rows = std::mem::take(&mut self.interlaced_rows);
+                ctx.encode_image(&rows, self.format, EncodeFlags::FINALIZE)?;
+            }
+            written
+        } else {
+            match ctx.encode_row(row) {
+                Ok(()) => written + 1,
+                Err(Error::Oi) => self.ihdr.height,
+                Err(error) => return Err(error),
+            }
+        };
+        self.state = if written >= self.ihdr.height {
+            WriterState::Finalized
+        } else {
+            WriterState::Rows { written }
+        };
+        Ok(())
+    }
+
+    /// Returns the number of rows written with [`write_row`], or the image height once all of the
+    /// image data has been written.
+    ///
+    /// [`write_row`]: method@Writer::write_row
+    pub fn rows_written(&self) -> u32 {
+        match self.state {
+            WriterState::Ready => 0,
+            WriterState::Rows { written } => written,
+            WriterState::Finalized => self.ihdr.height,
+        }
+    }
+
+    /// Returns the image header
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    /// Flushes the output and returns the underlying writer.
+    ///
+    /// Returns `Error::NotFinal` if the image data was not written, or if fewer rows than the
+    /// image height were written with [`write_row`].
+    ///
+    /// [`write_row`]: method@Writer::write_row
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.state != WriterState::Finalized {
+            return Err(Error::NotFinal);
+        }
+        let ctx = self.ctx.take().expect("writer already finished");
+        let mut writer = ctx.into_stream().expect("missing output stream");
+        io::Write::flush(&mut writer)?;
+        Ok(writer)
+    }
+}
+
+impl<W: io::Write> Drop for Writer<W> {
+    fn drop(&mut self) {
+        if let Some(ctx) = self.ctx.take() {
+            if let Some(mut writer) = ctx.into_stream() {
+                let _ = io::Write::flush(&mut writer);
+            }
+        }
+    }
+}
+
+/// Decodes a `png` and encodes it again row by row, e.g. to convert it to another format or
+/// recompress it.
+///
+/// libspng contexts can't both decode and encode, so this owns one of each and passes the rows
+/// between them. For non-interlaced input and output only a single row is buffered, interlaced
+/// input is decoded completely before the first row is encoded and interlaced output is encoded
+/// once the last row was written, see [`Writer::write_row`].
+pub struct Transcoder<R, W> {
+    decoder: Decoder<R>,
+    encoder: Encoder<W>,
+}
+
+impl<R, W> fmt::Debug for Transcoder<R, W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transcoder")
+            .field("decoder", &self.decoder)
+            .field("encoder", &self.encoder)
+            .finish()
+    }
+}
+
+impl<R, W> Transcoder<R, W> {
+    /// Create a transcoder that decodes `reader` to `output_format` and encodes the rows with
+    /// `encoder`.
+    ///
+    /// The dimensions, color type, bit depth, and source format of the `encoder` are replaced by
+    /// those of the decoded image, other options such as the compression level, interlacing, and
+    /// ancillary chunks are kept. Indexed images have no palette in the output, so they must be
+    /// decoded to an RGB or RGBA format.
+    pub fn new(reader: R, encoder: Encoder<W>, output_format: Format) -> Transcoder<R, W> {
+        let decoder = Decoder::new(reader).with_output_format(output_format);
+        Transcoder::from_decoder(decoder, encoder)
+    }
+
+    /// Create a transcoder from a configured `decoder`, see [`Transcoder::new`].
+    pub fn from_decoder(decoder: Decoder<R>, encoder: Encoder<W>) -> Transcoder<R, W> {
+        Transcoder { decoder, encoder }
+    }
+
+    /// Transcodes the image and returns the output image information and the flushed writer.
+    pub fn run(self) -> Result<(OutputInfo, W), Error>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        let mut reader = self.decoder.read_info()?;
+        let output_format = reader.output_format();
+        let ihdr = reader.raw_context().get_ihdr()?;
+        let output_info = OutputInfo::from_ihdr_format_buffer_size(
+            &ihdr,
+            output_format,
+            reader.output_buffer_size(),
+        )?;
+        let mut encoder = self.encoder;
+        let interlace_method = encoder.ihdr.interlace_method;
+        encoder.ihdr = Ihdr::from(&output_info);
+        encoder.ihdr.interlace_method = interlace_method;
+        encoder.source_format = match output_format {
+            Format::Raw => Format::Raw,
+            _ => Format::Png,
+        };
+        let mut writer = encoder.write_info()?;
+        let mut rows = reader.rows()?;
+        while let Some(row) = rows.next_row() {
+            writer.write_row(row?)?;
+        }
+        Ok((output_info, writer.finish()?))
+    }
+}
@@ -1,9 +1,12 @@
-use std::error::Error as StdError;
-use std::fmt;
+use core::ffi::CStr;
+use core::hash::{Hash, Hasher};
+use core::{fmt, mem};
+#[cfg(feature = "std")]
+use std::{error::Error as StdError, io, sync::Arc};
 
 use spng_sys as sys;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug)]
 #[repr(i32)]
 pub enum Error {
     IoError = sys::spng_errno_SPNG_IO_ERROR,
@@ -94,6 +97,330 @@ pub enum Error {
     NoDst = sys::spng_errno_SPNG_ENODST,
     OpState = sys::spng_errno_SPNG_EOPSTATE,
     NotFinal = sys::spng_errno_SPNG_ENOTFINAL,
+    /// A [`Limits`](crate::Limits) constraint was exceeded by `value`.
+    LimitsExceeded {
+        kind: LimitKind,
+        value: u64,
+        limit: u64,
+    },
+    /// An error returned by the stream. This replaces `IoError` when the stream's `io::Error`
+    /// was captured.
+    #[cfg(feature = "std")]
+    Stream(Arc<io::Error>),
+}
+
+/// The [`Limits`](crate::Limits) constraint reported by `Error::LimitsExceeded`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LimitKind {
+    /// `Limits::max_width`
+    Width,
+    /// `Limits::max_height`
+    Height,
+    /// `Limits::max_pixels`
+    Pixels,
+    /// `Limits::max_alloc_bytes`
+    AllocBytes,
+    /// `Limits::max_input_bytes`
+    InputBytes,
+}
+
+/// A coarse grouping of [`Error`] variants, see [`Error::category`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The stream failed or ended early: `IoError`, `IoEof`, `Io`, `Eof` and `Stream`
+    Io,
+    /// The PNG is malformed: bad signature, header fields, chunk layout, CRCs, ancillary
+    /// chunk contents or compressed image data
+    Format,
+    /// A size or memory limit was exceeded: `Mem`, `Overflow`, `UserWidth`, `UserHeight`,
+    /// `ChunkLimits`, `ChunkStdlen` and `LimitsExceeded`
+    Limits,
+    /// The PNG or the request is valid but not supported: `Fmt` and `ChunkUnknownCritical`
+    Unsupported,
+    /// An invalid argument, a call in the wrong state or a libspng internal failure
+    Internal,
+}
+
+impl Error {
+    /// Returns the libspng error code, e.g. `SPNG_ESIGNATURE` for `Error::Signature`.
+    ///
+    /// `Error::Stream` returns the code of `Error::IoError`, which it replaces.
+    /// `Error::LimitsExceeded` returns `SPNG_EUSER_WIDTH` or `SPNG_EUSER_HEIGHT` for the width and
+    /// height limits and `SPNG_EOVERFLOW` otherwise.
+    pub fn errno(&self) -> i32 {
+        match self {
+            Error::LimitsExceeded { kind, .. } => match kind {
+                LimitKind::Width => sys::spng_errno_SPNG_EUSER_WIDTH,
+                LimitKind::Height => sys::spng_errno_SPNG_EUSER_HEIGHT,
+                LimitKind::Pixels | LimitKind::AllocBytes | LimitKind::InputBytes => {
+                    sys::spng_errno_SPNG_EOVERFLOW
+                }
+            },
+            #[cfg(feature = "std")]
+            Error::Stream(_) => sys::spng_errno_SPNG_IO_ERROR,
+            // `Error` is `repr(i32)` so the discriminant is stored in the leading `i32`
+            _ => unsafe { *(self as *const Error as *const i32) },
+        }
+    }
+
+    /// Returns the libspng error message, e.g. `"invalid signature"` for `Error::Signature`.
+    ///
+    /// `Error::Stream` returns the message of `Error::IoError`, the stream's `io::Error` is
+    /// available from `source`.
+    pub fn message(&self) -> &'static str {
+        // `spng_strerror` returns static ASCII string literals
+        let message = unsafe { CStr::from_ptr(sys::spng_strerror(self.errno())) };
+        message.to_str().unwrap_or("unknown error")
+    }
+
+    /// Returns the name of the libspng error code, e.g. `"SPNG_ESIGNATURE"` for
+    /// `Error::Signature`, for logging or mapping errors across language bindings.
+    ///
+    /// The name matches [`errno`](Error::errno), so `Error::Stream` returns `"SPNG_IO_ERROR"`.
+    pub fn code_name(&self) -> &'static str {
+        use Error::*;
+        match self {
+            IoError => "SPNG_IO_ERROR",
+            IoEof => "SPNG_IO_EOF",
+            Inval => "SPNG_EINVAL",
+            Mem => "SPNG_EMEM",
+            Overflow => "SPNG_EOVERFLOW",
+            Signature => "SPNG_ESIGNATURE",
+            Width => "SPNG_EWIDTH",
+            Height => "SPNG_EHEIGHT",
+            UserWidth => "SPNG_EUSER_WIDTH",
+            UserHeight => "SPNG_EUSER_HEIGHT",
+            BitDepth => "SPNG_EBIT_DEPTH",
+            ColorType => "SPNG_ECOLOR_TYPE",
+            CompressionMethod => "SPNG_ECOMPRESSION_METHOD",
+            FilterMethod => "SPNG_EFILTER_METHOD",
+            InterlaceMethod => "SPNG_EINTERLACE_METHOD",
+            IhdrSize => "SPNG_EIHDR_SIZE",
+            Noihdr => "SPNG_ENOIHDR",
+            ChunkPos => "SPNG_ECHUNK_POS",
+            ChunkSize => "SPNG_ECHUNK_SIZE",
+            ChunkCrc => "SPNG_ECHUNK_CRC",
+            ChunkType => "SPNG_ECHUNK_TYPE",
+            ChunkUnknownCritical => "SPNG_ECHUNK_UNKNOWN_CRITICAL",
+            DupPlte => "SPNG_EDUP_PLTE",
+            DupChrm => "SPNG_EDUP_CHRM",
+            DupGama => "SPNG_EDUP_GAMA",
+            DupIccp => "SPNG_EDUP_ICCP",
+            DupSbit => "SPNG_EDUP_SBIT",
+            DupSrgb => "SPNG_EDUP_SRGB",
+            DupBkgd => "SPNG_EDUP_BKGD",
+            DupHist => "SPNG_EDUP_HIST",
+            DupTrns => "SPNG_EDUP_TRNS",
+            DupPhys => "SPNG_EDUP_PHYS",
+            DupTime => "SPNG_EDUP_TIME",
+            DupOffs => "SPNG_EDUP_OFFS",
+            DupExif => "SPNG_EDUP_EXIF",
+            Chrm => "SPNG_ECHRM",
+            PlteIdx => "SPNG_EPLTE_IDX",
+            TrnsColorType => "SPNG_ETRNS_COLOR_TYPE",
+            TrnsNoPlte => "SPNG_ETRNS_NO_PLTE",
+            Gama => "SPNG_EGAMA",
+            IccpName => "SPNG_EICCP_NAME",
+            IccpCompressionMethod => "SPNG_EICCP_COMPRESSION_METHOD",
+            Sbit => "SPNG_ESBIT",
+            Srgb => "SPNG_ESRGB",
+            Text => "SPNG_ETEXT",
+            TextKeyword => "SPNG_ETEXT_KEYWORD",
+            Ztxt => "SPNG_EZTXT",
+            ZtxtCompressionMethod => "SPNG_EZTXT_COMPRESSION_METHOD",
+            Itxt => "SPNG_EITXT",
+            ItxtCompressionFlag => "SPNG_EITXT_COMPRESSION_FLAG",
+            ItxtCompressionMethod => "SPNG_EITXT_COMPRESSION_METHOD",
+            ItxtLangTag => "SPNG_EITXT_LANG_TAG",
+            ItxtTranslatedKey => "SPNG_EITXT_TRANSLATED_KEY",
+            BkgdNoPlte => "SPNG_EBKGD_NO_PLTE",
+            BkgdPlteIdx => "SPNG_EBKGD_PLTE_IDX",
+            HistNoPlte => "SPNG_EHIST_NO_PLTE",
+            Phys => "SPNG_EPHYS",
+            SpltName => "SPNG_ESPLT_NAME",
+            SpltDupName => "SPNG_ESPLT_DUP_NAME",
+            SpltDepth => "SPNG_ESPLT_DEPTH",
+            Time => "SPNG_ETIME",
+            Offs => "SPNG_EOFFS",
+            Exif => "SPNG_EEXIF",
+            IdatTooShort => "SPNG_EIDAT_TOO_SHORT",
+            IdatStream => "SPNG_EIDAT_STREAM",
+            Zlib => "SPNG_EZLIB",
+            Filter => "SPNG_EFILTER",
+            Bufsiz => "SPNG_EBUFSIZ",
+            Io => "SPNG_EIO",
+            Eof => "SPNG_EOF",
+            BufSet => "SPNG_EBUF_SET",
+            Badstate => "SPNG_EBADSTATE",
+            Fmt => "SPNG_EFMT",
+            Flags => "SPNG_EFLAGS",
+            Chunkavail => "SPNG_ECHUNKAVAIL",
+            NcodeOnly => "SPNG_ENCODE_ONLY",
+            Oi => "SPNG_EOI",
+            Noplte => "SPNG_ENOPLTE",
+            ChunkLimits => "SPNG_ECHUNK_LIMITS",
+            ZlibInit => "SPNG_EZLIB_INIT",
+            ChunkStdlen => "SPNG_ECHUNK_STDLEN",
+            Internal => "SPNG_EINTERNAL",
+            CtxType => "SPNG_ECTXTYPE",
+            NoSrc => "SPNG_ENOSRC",
+            NoDst => "SPNG_ENODST",
+            OpState => "SPNG_EOPSTATE",
+            NotFinal => "SPNG_ENOTFINAL",
+            LimitsExceeded { kind, .. } => match kind {
+                LimitKind::Width => "SPNG_EUSER_WIDTH",
+                LimitKind::Height => "SPNG_EUSER_HEIGHT",
+                LimitKind::Pixels | LimitKind::AllocBytes | LimitKind::InputBytes => {
+                    "SPNG_EOVERFLOW"
+                }
+            },
+            #[cfg(feature = "std")]
+            Stream(_) => "SPNG_IO_ERROR",
+        }
+    }
+
+    /// Returns the [`ErrorCategory`] of the error, for handling or counting errors without
+    /// matching every variant.
+    pub fn category(&self) -> ErrorCategory {
+        use Error::*;
+        match self {
+            IoError | IoEof | Io | Eof => ErrorCategory::Io,
+            #[cfg(feature = "std")]
+            Stream(_) => ErrorCategory::Io,
+            Signature
+            | Width
+            | Height
+            | BitDepth
+            | ColorType
+            | CompressionMethod
+            | FilterMethod
+            | InterlaceMethod
+            | IhdrSize
+            | Noihdr
+            | ChunkPos
+            | ChunkSize
+            | ChunkCrc
+            | ChunkType
+            | DupPlte
+            | DupChrm
+            | DupGama
+            | DupIccp
+            | DupSbit
+            | DupSrgb
+            | DupBkgd
+            | DupHist
+            | DupTrns
+            | DupPhys
+            | DupTime
+            | DupOffs
+            | DupExif
+            | Chrm
+            | PlteIdx
+            | TrnsColorType
+            | TrnsNoPlte
+            | Gama
+            | IccpName
+            | IccpCompressionMethod
+            | Sbit
+            | Srgb
+            | Text
+            | TextKeyword
+            | Ztxt
+            | ZtxtCompressionMethod
+            | Itxt
+            | ItxtCompressionFlag
+            | ItxtCompressionMethod
+            | ItxtLangTag
+            | ItxtTranslatedKey
+            | BkgdNoPlte
+            | BkgdPlteIdx
+            | HistNoPlte
+            | Phys
+            | SpltName
+            | SpltDupName
+            | SpltDepth
+            | Time
+            | Offs
+            | Exif
+            | IdatTooShort
+            | IdatStream
+            | Zlib
+            | Filter
+            | Noplte => ErrorCategory::Format,
+            Mem
+            | Overflow
+            | UserWidth
+            | UserHeight
+            | ChunkLimits
+            | ChunkStdlen
+            | LimitsExceeded { .. } => ErrorCategory::Limits,
+            Fmt | ChunkUnknownCritical => ErrorCategory::Unsupported,
+            Inval | Bufsiz | BufSet | Badstate | Flags | Chunkavail | NcodeOnly | Oi | ZlibInit
+            | Internal | CtxType | NoSrc | NoDst | OpState | NotFinal => ErrorCategory::Internal,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Stream(Arc::new(error))
+    }
+}
+
+/// Compares the `io::ErrorKind` of `Error::Stream`, `UnexpectedEof` for `IoEof` and `Eof`, and
+/// `Other` for `IoError` and `Io`. Other variants never equal an `io::ErrorKind`.
+#[cfg(feature = "std")]
+impl PartialEq<io::ErrorKind> for Error {
+    fn eq(&self, kind: &io::ErrorKind) -> bool {
+        let own_kind = match self {
+            Error::Stream(error) => error.kind(),
+            Error::IoEof | Error::Eof => io::ErrorKind::UnexpectedEof,
+            Error::IoError | Error::Io => io::ErrorKind::Other,
+            _ => return false,
+        };
+        own_kind == *kind
+    }
+}
+
+#[cfg(feature = "std")]
+impl PartialEq<Error> for io::ErrorKind {
+    fn eq(&self, error: &Error) -> bool {
+        error == self
+    }
+}
+
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        match (self, other) {
+            #[cfg(feature = "std")]
+            (Error::Stream(a), Error::Stream(b)) => a.kind() == b.kind(),
+            (
+                Error::LimitsExceeded { kind, value, limit },
+                Error::LimitsExceeded {
+                    kind: other_kind,
+                    value: other_value,
+                    limit: other_limit,
+                },
+            ) => kind == other_kind && value == other_value && limit == other_limit,
+            _ => mem::discriminant(self) == mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for Error {}
+
+impl Hash for Error {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        #[cfg(feature = "std")]
+        if let Error::Stream(error) = self {
+            error.kind().hash(state);
+        }
+        if let Error::LimitsExceeded { kind, value, limit } = self {
+            (kind, value, limit).hash(state);
+        }
+    }
 }
 
 pub fn check_err(e: i32) -> Result<(), Error> {
@@ -188,6 +515,7 @@ pub fn check_err(e: i32) -> Result<(), Error> {
         sys::spng_errno_SPNG_EOPSTATE => Err(OpState),
         sys::spng_errno_SPNG_ENOTFINAL => Err(NotFinal),
         _ => {
+            #[cfg(feature = "std")]
             eprintln!("unknown spng error code: {}", e);
             Err(Inval)
         }
@@ -196,13 +524,83 @@ pub fn check_err(e: i32) -> Result<(), Error> {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let errno = *self as i32;
-        unsafe {
-            let ptr = sys::spng_strerror(errno);
-            let s = std::ffi::CStr::from_ptr(ptr);
-            write!(f, "{}", s.to_string_lossy())
+        match self {
+            Error::LimitsExceeded { kind, value, limit } => {
+                let kind = match kind {
+                    LimitKind::Width => "image width",
+                    LimitKind::Height => "image height",
+                    LimitKind::Pixels => "image pixel count",
+                    LimitKind::AllocBytes => "image buffer size",
+                    LimitKind::InputBytes => "input size",
+                };
+                write!(f, "{} {} exceeds the limit of {}", kind, value, limit)
+            }
+            _ => f.write_str(self.message()),
         }
     }
 }
 
-impl StdError for Error {}
+#[cfg(feature = "std")]
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Stream(error) => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errno() {
+        assert_eq!(sys::spng_errno_SPNG_ESIGNATURE, Error::Signature.errno());
+        assert_eq!(sys::spng_errno_SPNG_ENOTFINAL, Error::NotFinal.errno());
+        let stream = Error::from(io::Error::from(io::ErrorKind::Other));
+        assert_eq!(sys::spng_errno_SPNG_IO_ERROR, stream.errno());
+        assert_eq!(Err(Error::Oi), check_err(Error::Oi.errno()));
+    }
+
+    #[test]
+    fn code_name() {
+        assert_eq!("SPNG_ESIGNATURE", Error::Signature.code_name());
+        assert_eq!("SPNG_IO_EOF", Error::IoEof.code_name());
+        assert_eq!("SPNG_ENOTFINAL", Error::NotFinal.code_name());
+        let stream = Error::from(io::Error::from(io::ErrorKind::Other));
+        assert_eq!("SPNG_IO_ERROR", stream.code_name());
+        let limits = Error::LimitsExceeded {
+            kind: LimitKind::Height,
+            value: 2,
+            limit: 1,
+        };
+        assert_eq!("SPNG_EUSER_HEIGHT", limits.code_name());
+    }
+
+    #[test]
+    fn io_error_kind() {
+        assert_eq!(Error::IoEof, io::ErrorKind::UnexpectedEof);
+        assert_eq!(io::ErrorKind::UnexpectedEof, Error::Eof);
+        assert_eq!(Error::IoError, io::ErrorKind::Other);
+        let stream = Error::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert_eq!(stream, io::ErrorKind::BrokenPipe);
+        assert_ne!(stream, io::ErrorKind::Other);
+        assert_ne!(Error::Signature, io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn category() {
+        assert_eq!(ErrorCategory::Io, Error::IoEof.category());
+        let stream = Error::from(io::Error::from(io::ErrorKind::Other));
+        assert_eq!(ErrorCategory::Io, stream.category());
+        assert_eq!(ErrorCategory::Format, Error::Signature.category());
+        assert_eq!(ErrorCategory::Format, Error::ChunkCrc.category());
+        assert_eq!(ErrorCategory::Format, Error::DupTrns.category());
+        assert_eq!(ErrorCategory::Limits, Error::UserWidth.category());
+        assert_eq!(ErrorCategory::Limits, Error::ChunkLimits.category());
+        assert_eq!(ErrorCategory::Unsupported, Error::Fmt.category());
+        assert_eq!(ErrorCategory::Internal, Error::Badstate.category());
+        assert_eq!(ErrorCategory::Internal, Error::CtxType.category());
+    }
+}
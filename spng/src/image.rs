@@ -0,0 +1,335 @@
+use alloc::vec::Vec;
+
+use crate::{BitDepth, ColorType, Error, OutputInfo};
+
+/// A fully decoded image
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DecodedImage {
+    /// The output image information
+    pub info: OutputInfo,
+    /// The decoded pixel data
+    pub data: Vec<u8>,
+}
+
+impl DecodedImage {
+    /// Creates a new `DecodedImage` from decoded `data`
+    pub fn new(info: OutputInfo, data: Vec<u8>) -> DecodedImage {
+        DecodedImage { info, data }
+    }
+
+    /// Reverses the order of the rows in place, e.g. for uploading to an OpenGL texture where
+    /// the first row is at the bottom.
+    ///
+    /// Rows are swapped as whole `line_size` chunks, so packed 1, 2, and 4-bit formats are
+    /// flipped correctly. The middle row of an image with an odd height is left in place.
+    pub fn flip_y(&mut self) {
+        let line_size = self.info.line_size();
+        if line_size == 0 {
+            return;
+        }
+        let mut rows = self.data.chunks_exact_mut(line_size);
+        while let (Some(top), Some(bottom)) = (rows.next(), rows.next_back()) {
+            top.swap_with_slice(bottom);
+        }
+    }
+
+    /// Copies the `width` x `height` rectangle at (`x`, `y`) into a new image.
+    ///
+    /// Returns `None` if the rectangle is empty or out of bounds, or for packed 1, 2, and 4-bit
+    /// images, see [`unpack_to_g8`](DecodedImage::unpack_to_g8).
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<DecodedImage> {
+        let pixel_size = self.pixel_size()?;
+        let in_bounds = |start: u32, len: u32, max: u32| {
+            len > 0 && start.checked_add(len).is_some_and(|end| end <= max)
+        };
+        if !in_bounds(x, width, self.info.width) || !in_bounds(y, height, self.info.height) {
+            return None;
+        }
+        let start = x as usize * pixel_size;
+        let end = start + width as usize * pixel_size;
+        let data: Vec<u8> = self
+            .data
+            .chunks_exact(self.info.line_size())
+            .skip(y as usize)
+            .take(height as usize)
+            .flat_map(|row| &row[start..end])
+            .copied()
+            .collect();
+        let info = OutputInfo {
+            width,
+            height,
+            buffer_size: data.len(),
+            ..self.info
+        };
+        Some(DecodedImage { info, data })
+    }
+
+    /// Scales the image to `width` x `height` by copying the source pixel nearest to the
+    /// center of each output pixel.
+    ///
+    /// Returns `None` if either size is zero or the image is empty, or for packed 1, 2, and
+    /// 4-bit images, see [`unpack_to_g8`](DecodedImage::unpack_to_g8).
+    pub fn resize_nearest(&self, width: u32, height: u32) -> Option<DecodedImage> {
+        let pixel_size = self.pixel_size()?;
+        let (src_width, src_height) = (self.info.width as u64, self.info.height as u64);
+        if width == 0 || height == 0 || src_width == 0 || src_height == 0 {
+            return None;
+        }
+        let line_size = self.info.line_size();
+        let center = |i: u32, src: u64, dst: u32| (2 * i as u64 + 1) * src / (2 * dst as u64);
+        let mut data = Vec::with_capacity(width as usize * height as usize * pixel_size);
+        for y in 0..height {
+            let row = &self.data[center(y, src_height, height) as usize * line_size..];
+            for x in 0..width {
+                let start = center(x, src_width, width) as usize * pixel_size;
+                data.extend_from_slice(&row[start..start + pixel_size]);
+            }
+        }
+        Some(self.resized(width, height, data))
+    }
+
+    /// Scales an `8-bit` image to `width` x `height` with bilinear filtering.
+    ///
+    /// Each channel, including alpha, is interpolated independently, i.e. straight rather than
+    /// premultiplied alpha. Only the four nearest source pixels contribute to an output pixel,
+    /// so downscaling by more than half skips source pixels.
+    ///
+    /// Returns `None` if either size is zero or the image is empty, or if the image isn't
+    /// `8-bit`.
+    pub fn resize_bilinear(&self, width: u32, height: u32) -> Option<DecodedImage> {
+        if self.info.bit_depth != BitDepth::Eight {
+            return None;
+        }
+        let samples = self.info.color_type.samples();
+        let (src_width, src_height) = (self.info.width as u64, self.info.height as u64);
+        if width == 0 || height == 0 || src_width == 0 || src_height == 0 {
+            return None;
+        }
+        let line_size = self.info.line_size();
+        // Maps an output pixel center to the two nearest source pixels and the weight of the
+        // second one, in 1/256ths.
+        let neighbors = |i: u32, src: u64, dst: u32| {
+            let pos = ((2 * i as u64 + 1) * src * 256 / (2 * dst as u64)).saturating_sub(128);
+            let first = (pos >> 8).min(src - 1);
+            (
+                (first as usize, (first + 1).min(src - 1) as usize),
+                (pos & 0xff) as u32,
+            )
+        };
+        let mut data = Vec::with_capacity(width as usize * height as usize * samples);
+        for y in 0..height {
+            let ((y0, y1), fy) = neighbors(y, src_height, height);
+            let (top, bottom) = (&self.data[y0 * line_size..], &self.data[y1 * line_size..]);
+            for x in 0..width {
+                let ((x0, x1), fx) = neighbors(x, src_width, width);
+                for c in 0..samples {
+                    let (left, right) = (x0 * samples + c, x1 * samples + c);
+                    let blend = |row: &[u8]| row[left] as u32 * (256 - fx) + row[right] as u32 * fx;
+                    let value = blend(top) * (256 - fy) + blend(bottom) * fy;
+                    data.push(((value + 0x8000) >> 16) as u8);
+                }
+            }
+        }
+        Some(self.resized(width, height, data))
+    }
+
+    /// Returns the size of a pixel in bytes, or `None` for packed formats.
+    fn pixel_size(&self) -> Option<usize> {
+        let sample_size = match self.info.bit_depth {
+            BitDepth::Eight => 1,
+            BitDepth::Sixteen => 2,
+            _ => return None,
+        };
+        Some(self.info.color_type.samples() * sample_size)
+    }
+
+    fn resized(&self, width: u32, height: u32, data: Vec<u8>) -> DecodedImage {
+        let info = OutputInfo {
+            width,
+            height,
+            buffer_size: data.len(),
+            ..self.info
+        };
+        DecodedImage { info, data }
+    }
+
+    /// Returns `true` if every pixel has the maximum alpha value, stopping at the first pixel
+    /// that doesn't.
+    ///
+    /// Images without an alpha channel are always opaque. Transparency stored in a `tRNS` chunk
+    /// is not considered, decode with [`DecodeFlags::TRANSPARENCY`](crate::DecodeFlags) to an
+    /// alpha format to include it.
+    pub fn is_opaque(&self) -> bool {
+        let samples = self.info.color_type.samples();
+        let sample_size = match (self.info.color_type, self.info.bit_depth) {
+            (ColorType::GrayscaleAlpha | ColorType::TruecolorAlpha, BitDepth::Eight) => 1,
+            (ColorType::GrayscaleAlpha | ColorType::TruecolorAlpha, BitDepth::Sixteen) => 2,
+            _ => return true,
+        };
+        let pixel_size = samples * sample_size;
+        // The maximum 16-bit alpha is `0xffff` in either byte order
+        self.data
+            .chunks_exact(pixel_size)
+            .all(|pixel| pixel[pixel_size - sample_size..].iter().all(|&b| b == 0xff))
+    }
+
+    /// Returns the pixels as `[r, g, b, a]` if the image is `8-bit` RGBA.
+    #[cfg(feature = "bytemuck")]
+    pub fn as_rgba8(&self) -> Option<&[[u8; 4]]> {
+        self.cast_pixels(ColorType::TruecolorAlpha, BitDepth::Eight)
+    }
+
+    /// Returns the pixels as `[r, g, b, a]` if the image is `16-bit` RGBA in host-endian.
+    ///
+    /// Returns `None` if the buffer isn't aligned for `u16`.
+    #[cfg(feature = "bytemuck")]
+    pub fn as_rgba16(&self) -> Option<&[[u16; 4]]> {
+        self.cast_pixels(ColorType::TruecolorAlpha, BitDepth::Sixteen)
+    }
+
+    /// Returns the pixels as `[r, g, b]` if the image is `8-bit` RGB.
+    #[cfg(feature = "bytemuck")]
+    pub fn as_rgb8(&self) -> Option<&[[u8; 3]]> {
+        self.cast_pixels(ColorType::Truecolor, BitDepth::Eight)
+    }
+
+    /// Returns the pixels as `[gray, a]` if the image is `8-bit` grayscale with alpha.
+    #[cfg(feature = "bytemuck")]
+    pub fn as_ga8(&self) -> Option<&[[u8; 2]]> {
+        self.cast_pixels(ColorType::GrayscaleAlpha, BitDepth::Eight)
+    }
+
+    /// Returns the pixels as `[gray, a]` if the image is `16-bit` grayscale with alpha in
+    /// host-endian.
+    ///
+    /// Returns `None` if the buffer isn't aligned for `u16`.
+    #[cfg(feature = "bytemuck")]
+    pub fn as_ga16(&self) -> Option<&[[u16; 2]]> {
+        self.cast_pixels(ColorType::GrayscaleAlpha, BitDepth::Sixteen)
+    }
+
+    #[cfg(feature = "bytemuck")]
+    fn cast_pixels<P: bytemuck::Pod>(
+        &self,
+        color_type: ColorType,
+        bit_depth: BitDepth,
+    ) -> Option<&[P]> {
+        if self.info.color_type != color_type || self.info.bit_depth != bit_depth {
+            return None;
+        }
+        bytemuck::try_cast_slice(&self.data).ok()
+    }
+
+    /// Returns the pixels as a `height` x `width` x `samples` array if the image is `8-bit`.
+    #[cfg(feature = "ndarray")]
+    pub fn to_ndarray(&self) -> Option<ndarray::Array3<u8>> {
+        if self.info.bit_depth != BitDepth::Eight {
+            return None;
+        }
+        self.to_array3(self.data.clone())
+    }
+
+    /// Returns the pixels as a `height` x `width` x `samples` array if the image is `16-bit` in
+    /// host-endian.
+    #[cfg(feature = "ndarray")]
+    pub fn to_ndarray_u16(&self) -> Option<ndarray::Array3<u16>> {
+        if self.info.bit_depth != BitDepth::Sixteen {
+            return None;
+        }
+        let data = self
+            .data
+            .chunks_exact(2)
+            .map(|sample| u16::from_ne_bytes([sample[0], sample[1]]))
+            .collect();
+        self.to_array3(data)
+    }
+
+    /// Byte-aligned rows have no padding, so `data` is already in row-major order.
+    #[cfg(feature = "ndarray")]
+    fn to_array3<T>(&self, data: Vec<T>) -> Option<ndarray::Array3<T>> {
+        let shape = (
+            self.info.height as usize,
+            self.info.width as usize,
+            self.info.color_type.samples(),
+        );
+        ndarray::Array3::from_shape_vec(shape, data).ok()
+    }
+
+    /// Unpacks a grayscale image with a bit depth of 8 or less to one byte per pixel.
+    ///
+    /// Each output byte holds the unscaled sample value, e.g. `0` or `1` for a 1-bit
+    /// image. Rows are no longer padded to a byte boundary. Returns [`Error::ColorType`]
+    /// for non-grayscale images and [`Error::BitDepth`] for 16-bit images.
+    pub fn unpack_to_g8(&self) -> Result<DecodedImage, Error> {
+        if self.info.color_type != ColorType::Grayscale {
+            return Err(Error::ColorType);
+        }
+        let bits = match self.info.bit_depth {
+            BitDepth::One => 1,
+            BitDepth::Two => 2,
+            BitDepth::Four => 4,
+            BitDepth::Eight => return Ok(self.clone()),
+            BitDepth::Sixteen => return Err(Error::BitDepth),
+        };
+        let width = self.info.width as usize;
+        let height = self.info.height as usize;
+        let mask = (1u8 << bits) - 1;
+        let mut data = Vec::with_capacity(width * height);
+        if height > 0 {
+            for row in self.data.chunks_exact(self.info.line_size()) {
+                for x in 0..width {
+                    let bit = x * bits;
+                    let shift = 8 - bits - bit % 8;
+                    data.push((row[bit / 8] >> shift) & mask);
+                }
+            }
+        }
+        let info = OutputInfo {
+            bit_depth: BitDepth::Eight,
+            buffer_size: data.len(),
+            ..self.info
+        };
+        Ok(DecodedImage { info, data })
+    }
+}
+
+/// An image that may be missing rows, returned by [`decode_partial`](crate::decode_partial)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PartialImage {
+    /// The decoded image. Rows that could not be decoded are zeroed.
+    pub image: DecodedImage,
+    /// The number of rows, from the top, that were fully decoded
+    pub rows_decoded: u32,
+}
+
+impl PartialImage {
+    /// Returns `true` if every row of the image was decoded.
+    pub fn is_complete(&self) -> bool {
+        self.rows_decoded == self.image.info.height
+    }
+}
+
+/// Packs one-byte-per-pixel grayscale rows of `width` pixels into 1-bit rows.
+///
+/// Any non-zero sample is set. Each output row is padded to a byte boundary, matching the
+/// layout expected by [`Writer::write_image_data`](crate::Writer::write_image_data) for
+/// 1-bit grayscale images.
+pub fn pack_g1(rows: &[u8], width: u32) -> Vec<u8> {
+    let width = width as usize;
+    if width == 0 {
+        return Vec::new();
+    }
+    let line_size = width.div_ceil(8);
+    let mut out = Vec::with_capacity(line_size * (rows.len() / width));
+    for row in rows.chunks_exact(width) {
+        for bits in row.chunks(8) {
+            let byte = bits
+                .iter()
+                .enumerate()
+                .filter(|(_, &sample)| sample != 0)
+                .fold(0u8, |byte, (i, _)| byte | (0x80 >> i));
+            out.push(byte);
+        }
+    }
+    out
+}
@@ -1,12 +1,16 @@
-//! PNG image decoding
+//! PNG image decoding and encoding
 //!
 //! Rust bindings to [libspng](https://libspng.org).
 //!
 //! # Examples
 //!
 //! ```
-//! # static TEST_PNG: &[u8] = include_bytes!("../tests/test-001.png");
-//! let cursor = std::io::Cursor::new(TEST_PNG);
+//! # let pixels = (0..300 * 300 * 4).map(|i| i as u8).collect::<Vec<u8>>();
+//! # let mut png = Vec::new();
+//! # let mut writer = spng::Encoder::new(&mut png, 300, 300).write_info()?;
+//! # writer.write_image_data(&pixels)?;
+//! # writer.finish()?;
+//! let cursor = std::io::Cursor::new(png);
 //! let decoder = spng::Decoder::new(cursor);
 //! let mut reader = decoder.read_info()?;
 //! let info = reader.info();
@@ -21,17 +25,44 @@
 //! # Ok::<(), Box<dyn std::error::Error>>(())
 //! ```
 
-use std::convert::TryFrom;
-use std::io;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "alloc"))]
+compile_error!("the `alloc` feature is required");
+
+extern crate alloc;
+
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::FromStr;
 
 use spng_sys as sys;
 
+#[cfg(feature = "std")]
+mod arena;
+#[cfg(feature = "std")]
+mod decode;
+#[cfg(feature = "std")]
+mod encode;
 mod error;
+mod image;
 pub mod raw;
-
-pub use error::Error;
-
-use raw::RawContext;
+mod validate;
+
+#[cfg(feature = "std")]
+pub use arena::decode_with_arena;
+#[cfg(feature = "std")]
+pub use decode::{
+    decode, decode_full, decode_partial, decode_path, detect, sample_average_color, Background,
+    Decoder, FullImage, Reader, Replay, Rows, RowsBuffered, Scanlines,
+};
+#[cfg(feature = "std")]
+pub use encode::{EncodeChunks, Encoder, Transcoder, Writer};
+pub use error::{Error, ErrorCategory, LimitKind};
+pub use image::{pack_g1, DecodedImage, PartialImage};
+pub use validate::validate_structure;
+
+use raw::{chunk::Ihdr, RawContext};
 
 #[repr(u32)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -45,19 +76,140 @@ pub enum CrcAction {
 }
 
 /// PNG output format
+///
+/// libspng supports the following conversions when decoding:
+///
+/// | Format                    | Source                                  |
+/// |---------------------------|-----------------------------------------|
+/// | `Rgba8`, `Rgba16`, `Rgb8` | Any color type and bit depth            |
+/// | `G8`, `Ga8`               | Grayscale with a bit depth of 8 or less |
+/// | `Ga16`                    | `16-bit` grayscale                      |
+/// | `Png`, `Raw`              | Any color type and bit depth            |
+/// | `Auto`                    | Any color type and bit depth            |
+///
+/// Color images can't be converted to grayscale. See [`Format::supports_decode_from`].
+///
+/// libspng has no `16-bit` grayscale format. Decode `16-bit` grayscale images to `Png` for
+/// host-endian samples, or to `Ga16` to add an opaque alpha channel.
 #[repr(u32)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Format {
     Rgba8 = sys::spng_format_SPNG_FMT_RGBA8,
     Rgba16 = sys::spng_format_SPNG_FMT_RGBA16,
     Rgb8 = sys::spng_format_SPNG_FMT_RGB8,
+    /// Grayscale 8-bit, valid only for grayscale images with a bit depth of 8 or less.
+    ///
+    /// 1, 2, and 4-bit samples are unpacked to one byte per pixel. See also
+    /// [`DecodedImage::unpack_to_g8`] and [`pack_g1`].
     G8 = sys::spng_format_SPNG_FMT_G8,
+    /// Grayscale with alpha 8-bit, valid only for grayscale images with a bit depth of 8 or less.
     Ga8 = sys::spng_format_SPNG_FMT_GA8,
+    /// Grayscale with alpha 16-bit, valid only for `16-bit` grayscale images.
     Ga16 = sys::spng_format_SPNG_FMT_GA16,
     /// The PNG's format in host-endian
     Png = sys::spng_format_SPNG_FMT_PNG,
     /// The PNG's format in big-endian
     Raw = sys::spng_format_SPNG_FMT_RAW,
+    /// The explicit format best suited to the image, see [`Format::for_image`].
+    ///
+    /// This isn't a libspng format, it is resolved when decoding starts and
+    /// [`Reader::output_format`] returns the selected format.
+    Auto = 0,
+}
+
+impl Format {
+    /// Returns the canonical name, e.g. `"rgba16"`
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Format::Rgba8 => "rgba8",
+            Format::Rgba16 => "rgba16",
+            Format::Rgb8 => "rgb8",
+            Format::G8 => "g8",
+            Format::Ga8 => "ga8",
+            Format::Ga16 => "ga16",
+            Format::Png => "png",
+            Format::Raw => "raw",
+            Format::Auto => "auto",
+        }
+    }
+
+    /// Returns the color type and bit depth of the samples of this format, or `None` for
+    /// `Format::Png` and `Format::Raw` which use the layout of the PNG, and for `Format::Auto`.
+    pub const fn layout(self) -> Option<(ColorType, BitDepth)> {
+        match self {
+            Format::Rgba8 => Some((ColorType::RGBA, BitDepth::Eight)),
+            Format::Rgba16 => Some((ColorType::RGBA, BitDepth::Sixteen)),
+            Format::Rgb8 => Some((ColorType::RGB, BitDepth::Eight)),
+            Format::G8 => Some((ColorType::G, BitDepth::Eight)),
+            Format::Ga8 => Some((ColorType::GA, BitDepth::Eight)),
+            Format::Ga16 => Some((ColorType::GA, BitDepth::Sixteen)),
+            Format::Png | Format::Raw | Format::Auto => None,
+        }
+    }
+
+    /// Returns the size of a pixel in bytes, or `None` for `Format::Png` and `Format::Raw` which
+    /// depend on the PNG, and for `Format::Auto`.
+    pub const fn bytes_per_pixel(self) -> Option<usize> {
+        match self {
+            Format::Rgba8 => Some(4),
+            Format::Rgba16 => Some(8),
+            Format::Rgb8 => Some(3),
+            Format::G8 => Some(1),
+            Format::Ga8 => Some(2),
+            Format::Ga16 => Some(4),
+            Format::Png | Format::Raw | Format::Auto => None,
+        }
+    }
+
+    /// Returns `true` if libspng can decode an image with the given color type and bit depth
+    /// to this format.
+    pub fn supports_decode_from(self, color_type: ColorType, bit_depth: BitDepth) -> bool {
+        match self {
+            Format::Rgba8 | Format::Rgba16 | Format::Rgb8 | Format::Png | Format::Raw => true,
+            Format::Auto => true,
+            Format::G8 | Format::Ga8 => {
+                color_type == ColorType::Grayscale && bit_depth != BitDepth::Sixteen
+            }
+            Format::Ga16 => color_type == ColorType::Grayscale && bit_depth == BitDepth::Sixteen,
+        }
+    }
+
+    /// Returns the format `Format::Auto` selects for an image, the smallest explicit format
+    /// that keeps its channels and `16-bit` precision:
+    ///
+    /// | Source                                    | Format   |
+    /// |-------------------------------------------|----------|
+    /// | Grayscale, `16-bit`                       | `Ga16`   |
+    /// | Grayscale with a `tRNS` chunk             | `Ga8`    |
+    /// | Grayscale                                 | `G8`     |
+    /// | Any other `16-bit` image                  | `Rgba16` |
+    /// | Alpha, or a `tRNS` chunk                  | `Rgba8`  |
+    /// | Truecolor and indexed                     | `Rgb8`   |
+    ///
+    /// The `tRNS` chunk is only applied with [`DecodeFlags::TRANSPARENCY`].
+    pub const fn for_image(color_type: ColorType, bit_depth: BitDepth, trns: bool) -> Format {
+        match (color_type, bit_depth) {
+            (ColorType::Grayscale, BitDepth::Sixteen) => Format::Ga16,
+            (ColorType::Grayscale, _) if trns => Format::Ga8,
+            (ColorType::Grayscale, _) => Format::G8,
+            (_, BitDepth::Sixteen) => Format::Rgba16,
+            (ColorType::GrayscaleAlpha | ColorType::TruecolorAlpha, _) => Format::Rgba8,
+            _ if trns => Format::Rgba8,
+            _ => Format::Rgb8,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Format, Error> {
+        use Format::*;
+        [Rgba8, Rgba16, Rgb8, G8, Ga8, Ga16, Png, Raw, Auto]
+            .iter()
+            .copied()
+            .find(|format| format.as_str() == s)
+            .ok_or(Error::Fmt)
+    }
 }
 
 #[repr(u8)]
@@ -99,6 +251,17 @@ impl TryFrom<u8> for ColorType {
 }
 
 impl ColorType {
+    /// Returns the canonical name, e.g. `"truecolor-alpha"`
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Truecolor => "truecolor",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale-alpha",
+            ColorType::TruecolorAlpha => "truecolor-alpha",
+        }
+    }
+
     /// Returns the number of samples per pixel
     pub fn samples(self) -> usize {
         use ColorType::*;
@@ -111,6 +274,89 @@ impl ColorType {
     }
 }
 
+impl FromStr for ColorType {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<ColorType, Error> {
+        use ColorType::*;
+        [
+            Grayscale,
+            Truecolor,
+            Indexed,
+            GrayscaleAlpha,
+            TruecolorAlpha,
+        ]
+        .iter()
+        .copied()
+        .find(|color_type| color_type.as_str() == s)
+        .ok_or(Error::ColorType)
+    }
+}
+
+/// `sRGB` rendering intent
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RenderingIntent {
+    Perceptual = 0,
+    RelativeColorimetric = 1,
+    Saturation = 2,
+    AbsoluteColorimetric = 3,
+}
+
+impl TryFrom<u8> for RenderingIntent {
+    type Error = Error;
+    fn try_from(value: u8) -> Result<RenderingIntent, Error> {
+        use RenderingIntent::*;
+        match value {
+            0 => Ok(Perceptual),
+            1 => Ok(RelativeColorimetric),
+            2 => Ok(Saturation),
+            3 => Ok(AbsoluteColorimetric),
+            _ => Err(Error::Srgb),
+        }
+    }
+}
+
+/// Image interlace method
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum InterlaceMethod {
+    /// Rows are stored in order
+    None = 0,
+    /// Adam7 interlacing, which stores the image in seven passes of increasing detail
+    Adam7 = 1,
+}
+
+impl TryFrom<u8> for InterlaceMethod {
+    type Error = Error;
+    fn try_from(value: u8) -> Result<InterlaceMethod, Error> {
+        match value {
+            0 => Ok(InterlaceMethod::None),
+            1 => Ok(InterlaceMethod::Adam7),
+            _ => Err(Error::InterlaceMethod),
+        }
+    }
+}
+
+/// Ancillary chunk types with a getter on [`RawContext`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ChunkType {
+    Plte,
+    Trns,
+    Chrm,
+    Gama,
+    Iccp,
+    Sbit,
+    Srgb,
+    Text,
+    Bkgd,
+    Hist,
+    Phys,
+    Splt,
+    Time,
+    Offs,
+    Exif,
+}
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum BitDepth {
@@ -136,13 +382,46 @@ impl TryFrom<u8> for BitDepth {
     }
 }
 
+impl BitDepth {
+    /// Returns the canonical name, e.g. `"16"`
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            BitDepth::One => "1",
+            BitDepth::Two => "2",
+            BitDepth::Four => "4",
+            BitDepth::Eight => "8",
+            BitDepth::Sixteen => "16",
+        }
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<BitDepth, Error> {
+        let value = s.parse::<u8>().map_err(|_| Error::BitDepth)?;
+        BitDepth::try_from(value)
+    }
+}
+
 bitflags::bitflags! {
     /// Decoding flags
     #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
     pub struct DecodeFlags: u32 {
-        /// Apply transparency
+        /// Apply transparency from the `tRNS` chunk to the alpha channel of the output.
+        ///
+        /// This has no effect if the image has no `tRNS` chunk, if the image already has an alpha
+        /// channel (the chunk isn't allowed), or if the output format has no alpha channel
+        /// (`Rgb8`, `G8`, `Png`, and `Raw`).
         const TRANSPARENCY = sys::spng_decode_flags_SPNG_DECODE_TRNS;
-        /// Apply gamma correction
+        /// Apply gamma correction from the `gAMA` chunk.
+        ///
+        /// This has no effect if the image has no `gAMA` chunk or if the output format is `Png`
+        /// or `Raw`. Gamma correction changes the sample values, so it isn't part of
+        /// [`DecodeFlags::recommended`].
+        ///
+        /// libspng corrects for a display gamma of `2.2`, which can't be configured. The `gAMA`
+        /// chunk stores the encoding gamma, e.g. `1 / 2.2` for sRGB-like images, which are left
+        /// as-is. Each sample is mapped to `max * (sample / max) ^ (1 / (gamma * 2.2))`.
         const GAMMA = sys::spng_decode_flags_SPNG_DECODE_GAMMA;
         /// Initialize for progressive reads
         const PROGRESSIVE = sys::spng_decode_flags_SPNG_DECODE_PROGRESSIVE;
@@ -151,29 +430,131 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    /// Encoding flags
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub struct EncodeFlags: u32 {
+        /// Initialize for progressive writes
+        const PROGRESSIVE = sys::spng_encode_flags_SPNG_ENCODE_PROGRESSIVE;
+        /// Finalize the PNG after encoding the image
+        const FINALIZE = sys::spng_encode_flags_SPNG_ENCODE_FINALIZE;
+    }
+}
+
+impl DecodeFlags {
+    /// The flags that decode an image as it is meant to be displayed without altering the stored
+    /// sample values, currently only [`DecodeFlags::TRANSPARENCY`].
+    ///
+    /// ```
+    /// use spng::{raw::chunk::Trns, BitDepth, ColorType, DecodeFlags, Format};
+    ///
+    /// // A 2x1 grayscale image where gray level `0` is transparent
+    /// let trns = Trns {
+    ///     gray: 0,
+    ///     red: 0,
+    ///     green: 0,
+    ///     blue: 0,
+    ///     n_type3_entries: 0,
+    ///     type3_alpha: [0; 256],
+    /// };
+    /// let mut png = Vec::new();
+    /// let mut writer = spng::Encoder::new(&mut png, 2, 1)
+    ///     .with_color_type(ColorType::Grayscale)
+    ///     .with_bit_depth(BitDepth::Eight)
+    ///     .with_chunks(spng::EncodeChunks::new().with_trns(trns))
+    ///     .write_info()?;
+    /// writer.write_image_data(&[0, 128])?;
+    /// writer.finish()?;
+    ///
+    /// let decode = |flags| -> Result<Vec<u8>, spng::Error> {
+    ///     let mut reader = spng::Decoder::new(png.as_slice())
+    ///         .with_output_format(Format::Ga8)
+    ///         .with_decode_flags(flags)
+    ///         .read_info()?;
+    ///     let mut out = vec![0; reader.output_buffer_size()];
+    ///     reader.next_frame(&mut out)?;
+    ///     Ok(out)
+    /// };
+    /// assert_eq!(vec![0, 0, 128, 255], decode(DecodeFlags::recommended())?);
+    /// assert_eq!(vec![0, 255, 128, 255], decode(DecodeFlags::empty())?);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub const fn recommended() -> DecodeFlags {
+        DecodeFlags::TRANSPARENCY
+    }
+}
+
+/// Formats the set flags by name, e.g. `"TRANSPARENCY | GAMMA"`. Unnamed bits are written as
+/// hex and an empty set is written as an empty string.
+impl fmt::Display for DecodeFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        bitflags::parser::to_writer(self, f)
+    }
+}
+
+/// Formats the set flags by name, e.g. `"PROGRESSIVE | FINALIZE"`. Unnamed bits are written as
+/// hex and an empty set is written as an empty string.
+impl fmt::Display for EncodeFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        bitflags::parser::to_writer(self, f)
+    }
+}
+
 bitflags::bitflags! {
     #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
     pub struct ContextFlags: u32 {
         /// Ignore checksum in `DEFLATE` streams
         const IGNORE_ADLER32 = sys::spng_ctx_flags_SPNG_CTX_IGNORE_ADLER32;
+        /// Create an encoder context
+        const ENCODER = sys::spng_ctx_flags_SPNG_CTX_ENCODER;
     }
 }
 
 #[repr(u32)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum SpngOption {
+    KeepUnknownChunks = sys::spng_option_SPNG_KEEP_UNKNOWN_CHUNKS,
     ZlibCompressionLevel = sys::spng_option_SPNG_IMG_COMPRESSION_LEVEL,
     ZlibWindowBits = sys::spng_option_SPNG_IMG_WINDOW_BITS,
+    /// The [`FilterChoice`] bits used when encoding
+    FilterChoice = sys::spng_option_SPNG_FILTER_CHOICE,
     ChunkCountLimit = sys::spng_option_SPNG_CHUNK_COUNT_LIMIT,
+    /// Encode to an internal buffer instead of a stream, see [`RawContext::take_png_buffer`]
+    ///
+    /// [`RawContext::take_png_buffer`]: crate::raw::RawContext::take_png_buffer
+    EncodeToBuffer = sys::spng_option_SPNG_ENCODE_TO_BUFFER,
+}
+
+bitflags::bitflags! {
+    /// Scanline filters the encoder may choose from. The empty set disables filtering.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub struct FilterChoice: u32 {
+        const NONE = sys::spng_filter_choice_SPNG_FILTER_CHOICE_NONE;
+        const SUB = sys::spng_filter_choice_SPNG_FILTER_CHOICE_SUB;
+        const UP = sys::spng_filter_choice_SPNG_FILTER_CHOICE_UP;
+        const AVG = sys::spng_filter_choice_SPNG_FILTER_CHOICE_AVG;
+        const PAETH = sys::spng_filter_choice_SPNG_FILTER_CHOICE_PAETH;
+        /// Every filter, the default
+        const ALL = sys::spng_filter_choice_SPNG_FILTER_CHOICE_ALL;
+    }
 }
 
 /// Decoding limits
+///
+/// Exceeding a limit returns `Error::LimitsExceeded` reporting which limit was exceeded.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Limits {
     /// Maximum image width
     pub max_width: u32,
     /// Maximum image height
     pub max_height: u32,
+    /// Maximum number of pixels, `width * height`
+    pub max_pixels: u64,
+    /// Maximum size of the decoded image buffer in bytes. This is not checked by the `Encoder`.
+    pub max_alloc_bytes: u64,
+    /// Maximum number of bytes the `Decoder` reads from its input, bounding the work done on
+    /// untrusted input regardless of the image dimensions. This is not checked by the `Encoder`.
+    pub max_input_bytes: u64,
 }
 
 const PNG_U32_MAX: u32 = u32::MAX / 2 - 1;
@@ -183,18 +564,45 @@ impl Default for Limits {
         Limits {
             max_width: PNG_U32_MAX,
             max_height: PNG_U32_MAX,
+            max_pixels: u64::MAX,
+            max_alloc_bytes: u64::MAX,
+            max_input_bytes: u64::MAX,
         }
     }
 }
 
-/// PNG decoder
-#[derive(Debug)]
-pub struct Decoder<R> {
-    reader: R,
-    limits: Limits,
-    context_flags: ContextFlags,
-    decode_flags: DecodeFlags,
-    output_format: Format,
+impl Limits {
+    /// Checks the image dimensions and, if known, the decoded buffer size against the limits.
+    #[cfg(feature = "std")]
+    pub(crate) fn check(
+        &self,
+        width: u32,
+        height: u32,
+        alloc_bytes: Option<usize>,
+    ) -> Result<(), Error> {
+        let exceeded = |kind, value: u64, limit: u64| {
+            if value > limit {
+                Err(Error::LimitsExceeded { kind, value, limit })
+            } else {
+                Ok(())
+            }
+        };
+        exceeded(LimitKind::Width, width as u64, self.max_width as u64)?;
+        exceeded(LimitKind::Height, height as u64, self.max_height as u64)?;
+        exceeded(
+            LimitKind::Pixels,
+            width as u64 * height as u64,
+            self.max_pixels,
+        )?;
+        if let Some(alloc_bytes) = alloc_bytes {
+            exceeded(
+                LimitKind::AllocBytes,
+                alloc_bytes as u64,
+                self.max_alloc_bytes,
+            )?;
+        }
+        Ok(())
+    }
 }
 
 /// Decoded output image information
@@ -213,19 +621,79 @@ pub struct OutputInfo {
 }
 
 impl OutputInfo {
-    /// The width of each row or scanline
+    /// The width of each row or scanline, or `0` if the height is `0`
     pub fn line_size(&self) -> usize {
-        self.buffer_size / self.height as usize
+        self.buffer_size
+            .checked_div(self.height as usize)
+            .unwrap_or(0)
     }
 
     pub fn buffer_size(&self) -> usize {
         self.buffer_size
     }
+
+    /// Returns `true` if the dimensions, color type, and bit depth equal `info`, ignoring the
+    /// buffer size.
+    ///
+    /// The output color type and bit depth are those of the output format, so this is only
+    /// `true` for a converted format if it matches the PNG's own format.
+    pub fn matches_info(&self, info: &Info) -> bool {
+        self.width == info.width
+            && self.height == info.height
+            && self.color_type == info.color_type
+            && self.bit_depth == info.bit_depth
+    }
+}
+
+impl From<OutputInfo> for Info {
+    /// Decoded output is never interlaced, so the interlace method is always
+    /// `InterlaceMethod::None`.
+    fn from(output_info: OutputInfo) -> Info {
+        Info {
+            width: output_info.width,
+            height: output_info.height,
+            color_type: output_info.color_type,
+            bit_depth: output_info.bit_depth,
+            interlace_method: InterlaceMethod::None,
+        }
+    }
+}
+
+impl From<&Info> for Ihdr {
+    /// Builds an `IHDR` from `info` with the default compression and filter methods.
+    fn from(info: &Info) -> Ihdr {
+        sys::spng_ihdr {
+            width: info.width,
+            height: info.height,
+            bit_depth: info.bit_depth as u8,
+            color_type: info.color_type as u8,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: info.interlace_method as u8,
+        }
+    }
+}
+
+impl From<&OutputInfo> for Ihdr {
+    /// Builds an `IHDR` from the output color type and bit depth with the default compression,
+    /// filter, and interlace methods.
+    fn from(output_info: &OutputInfo) -> Ihdr {
+        Ihdr::from(&Info::from(*output_info))
+    }
 }
 
 impl OutputInfo {
-    fn from_ihdr_format_buffer_size(
-        ihdr: &sys::spng_ihdr,
+    /// Creates the output information for decoding an image with the header `ihdr` to
+    /// `output_format`, e.g. from [`RawContext::get_ihdr`] and
+    /// [`RawContext::decoded_image_size`].
+    ///
+    /// Returns `Error::BitDepth` or `Error::ColorType` if the header is invalid. The dimensions
+    /// aren't validated, libspng rejects a zero width or height when reading the header.
+    ///
+    /// Returns `Error::Fmt` for `Format::Auto`, resolve it first with
+    /// [`RawContext::resolve_format`].
+    pub fn from_ihdr_format_buffer_size(
+        ihdr: &Ihdr,
         output_format: Format,
         output_buffer_size: usize,
     ) -> Result<OutputInfo, Error> {
@@ -233,6 +701,7 @@ impl OutputInfo {
             Format::Png | Format::Raw => BitDepth::try_from(ihdr.bit_depth)?,
             Format::Rgb8 | Format::Rgba8 | Format::G8 | Format::Ga8 => BitDepth::Eight,
             Format::Rgba16 | Format::Ga16 => BitDepth::Sixteen,
+            Format::Auto => return Err(Error::Fmt),
         };
         let color_type = match output_format {
             Format::Png | Format::Raw => ColorType::try_from(ihdr.color_type)?,
@@ -241,6 +710,7 @@ impl OutputInfo {
             Format::Rgba16 => ColorType::TruecolorAlpha,
             Format::G8 => ColorType::Grayscale,
             Format::Ga8 | Format::Ga16 => ColorType::GrayscaleAlpha,
+            Format::Auto => return Err(Error::Fmt),
         };
         Ok(OutputInfo {
             bit_depth,
@@ -263,151 +733,51 @@ pub struct Info {
     pub color_type: ColorType,
     /// The per-component bit depth
     pub bit_depth: BitDepth,
+    /// The interlace method
+    pub interlace_method: InterlaceMethod,
+}
+
+impl TryFrom<&Ihdr> for Info {
+    type Error = Error;
+    fn try_from(ihdr: &Ihdr) -> Result<Info, Error> {
+        Info::from_ihdr(ihdr)
+    }
 }
 
 impl Info {
-    fn from_ihdr(header: &sys::spng_ihdr) -> Result<Info, Error> {
+    fn from_ihdr(header: &Ihdr) -> Result<Info, Error> {
         Ok(Info {
             width: header.width,
             height: header.height,
             bit_depth: BitDepth::try_from(header.bit_depth)?,
             color_type: ColorType::try_from(header.color_type)?,
+            interlace_method: InterlaceMethod::try_from(header.interlace_method)?,
         })
     }
 }
 
-#[derive(Debug)]
-/// PNG reader
-pub struct Reader<R> {
-    ctx: RawContext<R>,
-    ihdr: sys::spng_ihdr,
-    output_buffer_size: usize,
-    output_format: Format,
-    decode_flags: DecodeFlags,
-}
-
-impl<R> Decoder<R> {
-    /// Create a new `png` decoder with the default limits
-    pub fn new(reader: R) -> Decoder<R> {
-        let decode_flags = DecodeFlags::empty();
-        let context_flags = ContextFlags::empty();
-        let output_format = Format::Png;
-        let limits = Limits::default();
-        Decoder {
-            reader,
-            limits,
-            context_flags,
-            decode_flags,
-            output_format,
-        }
-    }
-
-    pub fn with_limits(mut self, limits: Limits) -> Decoder<R> {
-        self.limits = limits;
-        self
-    }
-
-    pub fn with_context_flags(mut self, context_flags: ContextFlags) -> Decoder<R> {
-        self.context_flags = context_flags;
-        self
-    }
-
-    pub fn with_decode_flags(mut self, decode_flags: DecodeFlags) -> Decoder<R> {
-        self.decode_flags = decode_flags;
-        self
-    }
-
-    pub fn with_output_format(mut self, output_format: Format) -> Decoder<R> {
-        self.output_format = output_format;
-        self
-    }
-
-    /// Set the limits
-    pub fn set_limits(&mut self, limits: Limits) {
-        self.limits = limits;
-    }
-
-    /// Set the decoding flags
-    pub fn set_decode_flags(&mut self, decode_flags: DecodeFlags) {
-        self.decode_flags = decode_flags;
-    }
-
-    /// Set the output image format
-    pub fn set_output_format(&mut self, output_format: Format) {
-        self.output_format = output_format;
-    }
-
-    pub fn set_context_flags(&mut self, context_flags: ContextFlags) {
-        self.context_flags = context_flags;
-    }
-
-    /// Read the `png` header and initialize decoding.
-    pub fn read_info(self) -> Result<Reader<R>, Error>
-    where
-        R: io::Read,
-    {
-        let mut ctx = RawContext::with_flags(self.context_flags)?;
-        ctx.set_image_limits(self.limits.max_width, self.limits.max_height)?;
-        ctx.set_png_stream(self.reader)?;
-        let ihdr = ctx.get_ihdr()?;
-        let output_buffer_size = ctx.decoded_image_size(self.output_format)?;
-        let reader = Reader {
-            ctx,
-            ihdr,
-            output_format: self.output_format,
-            decode_flags: self.decode_flags,
-            output_buffer_size,
-        };
-
-        Ok(reader)
-    }
-}
-
-impl<R> Reader<R> {
-    /// Returns input information
-    pub fn info(&self) -> Info {
-        Info::from_ihdr(&self.ihdr).expect("invalid ihdr")
+/// Decode `png` data from a buffer.
+///
+/// This is available without the `std` feature, unlike [`Decoder`] and the other streaming APIs
+/// that depend on `std::io`.
+///
+/// The decoded image is always a new buffer, even for `Format::Png` and `Format::Raw`. PNG
+/// image data is a zlib stream of filtered rows, each with a leading filter type byte, so it
+/// never matches the decoded layout even when stored uncompressed.
+pub fn decode_bytes(data: &[u8], output_format: Format) -> Result<DecodedImage, Error> {
+    let mut ctx = RawContext::new()?;
+    ctx.set_png_buffer(data)?;
+    let output_format = ctx.resolve_format(output_format)?;
+    let ihdr = ctx.get_ihdr()?;
+    let info = Info::from_ihdr(&ihdr)?;
+    if !output_format.supports_decode_from(info.color_type, info.bit_depth) {
+        return Err(Error::Fmt);
     }
-
-    /// Returns the minimum buffer size required for `next_frame`
-    #[inline]
-    pub fn output_buffer_size(&self) -> usize {
-        self.output_buffer_size
-    }
-
-    /// Decodes the next frame of the `png`. This currently may only be called once.
-    pub fn next_frame(&mut self, output: &mut [u8]) -> Result<OutputInfo, Error> {
-        self.ctx
-            .decode_image(output, self.output_format, self.decode_flags)?;
-        let ihdr = self.ctx.get_ihdr()?;
-        let output_info = OutputInfo::from_ihdr_format_buffer_size(
-            &ihdr,
-            self.output_format,
-            self.output_buffer_size(),
-        )?;
-        Ok(output_info)
-    }
-
-    /// Returns a reference to the `RawContext`.
-    pub fn raw_context(&self) -> &RawContext<R> {
-        &self.ctx
-    }
-}
-
-/// Decode `png` data.
-pub fn decode<R>(reader: R, output_format: Format) -> Result<(OutputInfo, Vec<u8>), Error>
-where
-    R: io::Read,
-{
-    let decoder = Decoder::new(reader).with_output_format(output_format);
-    let mut reader = decoder.read_info()?;
-    let mut out = Vec::new();
-    out.reserve_exact(reader.output_buffer_size());
-    unsafe {
-        out.set_len(reader.output_buffer_size());
-    }
-    let out_info = reader.next_frame(&mut out)?;
-    Ok((out_info, out))
+    let buffer_size = ctx.decoded_image_size(output_format)?;
+    let mut out = alloc::vec![0; buffer_size];
+    ctx.decode_image(&mut out, output_format, DecodeFlags::empty())?;
+    let out_info = OutputInfo::from_ihdr_format_buffer_size(&ihdr, output_format, buffer_size)?;
+    Ok(DecodedImage::new(out_info, out))
 }
 
 /// Returns the `libspng` version: `(major, minor, patch)`
@@ -418,3 +788,38 @@ pub fn version() -> (u32, u32, u32) {
         spng_sys::SPNG_VERSION_PATCH,
     )
 }
+
+/// The 8-byte signature every `png` starts with
+const PNG_SIGNATURE: [u8; 8] = *b"\x89PNG\r\n\x1a\n";
+
+/// Returns `true` if `bytes` starts with the `png` signature.
+///
+/// This only checks the signature, the rest of the data may still be invalid.
+pub fn is_png(bytes: &[u8]) -> bool {
+    bytes.starts_with(&PNG_SIGNATURE)
+}
+
+/// Returns `true` if `libspng` can create encoder contexts.
+///
+/// The vendored `libspng` always supports encoding, but a minimal or system build may not, in
+/// which case creating a context with [`ContextFlags::ENCODER`] fails. This probes by creating
+/// and freeing an encoder context.
+pub fn encoder_available() -> bool {
+    RawContext::<&[u8]>::with_flags(ContextFlags::ENCODER).is_ok()
+}
+
+/// Returns the `libspng` version string, e.g. `"0.7.4"`, as reported by the library. Unlike
+/// [`version`] it may carry a suffix, e.g. for a development build.
+pub fn version_str() -> &'static str {
+    // The version string is a static in libspng
+    let version = unsafe { core::ffi::CStr::from_ptr(sys::spng_version_string()) };
+    version.to_str().unwrap_or_default()
+}
+
+/// Returns the SIMD instruction set `libspng` was compiled to use, e.g. `"sse2"`.
+///
+/// This is fixed at build time, build with `-C target-cpu=native` or
+/// `-C target-feature=+sse4.1` to use SSE4.1. See [`spng_sys::SPNG_SIMD`].
+pub fn simd_level() -> &'static str {
+    spng_sys::SPNG_SIMD
+}
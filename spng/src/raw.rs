@@ -2,33 +2,134 @@
 
 use crate::{
     error::{check_err, Error},
-    ContextFlags, CrcAction, DecodeFlags, Format, SpngOption,
+    BitDepth, ChunkType, ColorType, ContextFlags, CrcAction, DecodeFlags, EncodeFlags, Format,
+    InterlaceMethod, RenderingIntent, SpngOption,
 };
 
 use self::chunk::*;
 
+#[cfg(feature = "std")]
+use crate::error::LimitKind;
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::convert::TryFrom;
+use core::{
+    cell::Cell,
+    fmt,
+    marker::PhantomData,
+    mem,
+    mem::MaybeUninit,
+    ops::Deref,
+    ptr::{self, NonNull},
+    slice,
+};
 use spng_sys as sys;
-use std::{io, marker::PhantomData, mem, mem::MaybeUninit, ptr::NonNull, slice};
+#[cfg(feature = "std")]
+use std::{
+    any::Any,
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+/// The user stream and the last error or panic it raised, or an owned input buffer.
+///
+/// Streams require `std`, without it a `RawContext` only decodes from a buffer.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+struct Stream<S> {
+    inner: S,
+    #[cfg(feature = "std")]
+    error: Option<Error>,
+    #[cfg(feature = "std")]
+    panic: Option<Box<dyn Any + Send>>,
+    /// Bytes delivered to libspng by `read_fn`
+    #[cfg(feature = "std")]
+    read: u64,
+    #[cfg(feature = "std")]
+    max_read: u64,
+}
+
+impl<S> Stream<S> {
+    fn new(inner: S) -> Stream<S> {
+        Stream {
+            inner,
+            #[cfg(feature = "std")]
+            error: None,
+            #[cfg(feature = "std")]
+            panic: None,
+            #[cfg(feature = "std")]
+            read: 0,
+            #[cfg(feature = "std")]
+            max_read: u64::MAX,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S> Stream<S> {
+    /// Runs `f` on the stream, converting an `io::Error` or a panic into `SPNG_IO_ERROR`.
+    /// Unwinding across the FFI boundary is undefined behavior, so a panic is stored and resumed
+    /// by [`RawContext::check`] once libspng returns.
+    fn call<F: FnOnce(&mut S) -> io::Result<libc::c_int>>(&mut self, f: F) -> libc::c_int {
+        let inner = &mut self.inner;
+        match panic::catch_unwind(AssertUnwindSafe(|| f(inner))) {
+            Ok(Ok(ret)) => ret,
+            Ok(Err(error)) => {
+                self.error = Some(Error::Stream(Arc::new(error)));
+                sys::spng_errno_SPNG_IO_ERROR
+            }
+            Err(payload) => {
+                self.panic = Some(payload);
+                sys::spng_errno_SPNG_IO_ERROR
+            }
+        }
+    }
+}
 
+#[cfg(feature = "std")]
 unsafe extern "C" fn read_fn<R: io::Read>(
     _: *mut sys::spng_ctx,
     user: *mut libc::c_void,
     dest: *mut libc::c_void,
     len: usize,
 ) -> libc::c_int {
-    let reader: &mut R = &mut *(user as *mut R as *mut _);
+    let stream: &mut Stream<R> = &mut *(user as *mut Stream<R>);
+    let read = stream.read.saturating_add(len as u64);
+    if read > stream.max_read {
+        stream.error = Some(Error::LimitsExceeded {
+            kind: LimitKind::InputBytes,
+            value: read,
+            limit: stream.max_read,
+        });
+        return sys::spng_errno_SPNG_IO_ERROR;
+    }
+    stream.read = read;
     let dest = slice::from_raw_parts_mut(dest as *mut u8, len);
-    let mut offset = 0;
-    while offset < len {
-        let buf = &mut dest[offset..];
-        let ret = reader.read(buf);
-        match ret {
-            Ok(0) => return sys::spng_errno_SPNG_IO_EOF,
-            Ok(n) => offset += n,
-            Err(_) => return sys::spng_errno_SPNG_IO_ERROR,
+    stream.call(|inner| {
+        let mut offset = 0;
+        while offset < len {
+            match inner.read(&mut dest[offset..])? {
+                0 => return Ok(sys::spng_errno_SPNG_IO_EOF),
+                n => offset += n,
+            }
         }
-    }
-    sys::spng_errno_SPNG_OK
+        Ok(sys::spng_errno_SPNG_OK)
+    })
+}
+
+#[cfg(feature = "std")]
+unsafe extern "C" fn write_fn<W: io::Write>(
+    _: *mut sys::spng_ctx,
+    user: *mut libc::c_void,
+    src: *mut libc::c_void,
+    len: usize,
+) -> libc::c_int {
+    let stream: &mut Stream<W> = &mut *(user as *mut Stream<W>);
+    let src = slice::from_raw_parts(src as *const u8, len);
+    stream.call(|inner| {
+        // `write_all` retries short writes and `ErrorKind::Interrupted`
+        inner.write_all(src)?;
+        Ok(sys::spng_errno_SPNG_OK)
+    })
 }
 
 /// Helper trait for converting optional ancillary chunks into `Option<T>`.
@@ -65,14 +166,51 @@ impl<T> ChunkAvail<T> for Result<T, Error> {
     }
 }
 
-/// The raw decoding context.
+/// The raw decoding or encoding context.
+///
+/// libspng keeps no global state, so independent contexts may be used concurrently on different
+/// threads.
+///
+/// A context decodes or encodes a single image. libspng has no way to reset a context, the input
+/// and output can only be set once, so contexts can't be pooled and reused. Creating one is cheap,
+/// the zlib state is only allocated once the image data is reached.
 ///
 /// * <https://libspng.org/>
 /// * <http://www.libpng.org/pub/png/spec/1.1/PNG-Contents.html>
-#[derive(Debug)]
 pub struct RawContext<R> {
     raw: *mut sys::spng_ctx,
-    reader: Option<NonNull<R>>,
+    stream: Option<NonNull<Stream<R>>>,
+    image_sizes: Cell<[Option<usize>; FORMAT_COUNT]>,
+    /// Frees buffers libspng hands over to the caller
+    free_fn: unsafe extern "C" fn(*mut libc::c_void),
+    /// The text set with `set_text`, libspng only stores pointers to it
+    text: Vec<Vec<u8>>,
+    #[cfg(test)]
+    image_size_queries: Cell<usize>,
+}
+
+const FORMAT_COUNT: usize = 8;
+
+fn format_slot(format: Format) -> usize {
+    match format {
+        Format::Rgba8 => 0,
+        Format::Rgba16 => 1,
+        Format::Rgb8 => 2,
+        Format::G8 => 3,
+        Format::Ga8 => 4,
+        Format::Ga16 => 5,
+        Format::Png => 6,
+        Format::Raw => 7,
+        Format::Auto => unreachable!("`Format::Auto` is resolved before caching"),
+    }
+}
+
+impl<R> fmt::Debug for RawContext<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RawContext")
+            .field("has_stream", &self.stream.is_some())
+            .finish_non_exhaustive()
+    }
 }
 
 impl<R> Drop for RawContext<R> {
@@ -82,9 +220,9 @@ impl<R> Drop for RawContext<R> {
                 sys::spng_ctx_free(self.raw);
             }
         }
-        if let Some(reader) = self.reader {
+        if let Some(stream) = self.stream {
             unsafe {
-                drop(Box::from_raw(reader.as_ptr()));
+                drop(Box::from_raw(stream.as_ptr()));
             }
         }
     }
@@ -96,24 +234,135 @@ impl<R> RawContext<R> {
     }
 
     pub fn with_flags(flags: ContextFlags) -> Result<RawContext<R>, Error> {
+        unsafe { RawContext::from_raw(sys::spng_ctx_new(flags.bits() as _)) }
+    }
+
+    /// Creates a context that allocates with `alloc`, which libspng copies.
+    ///
+    /// Safety: the allocation functions must be usable until the context is dropped.
+    #[cfg(feature = "std")]
+    pub(crate) unsafe fn with_alloc(
+        alloc: &sys::spng_alloc,
+        flags: ContextFlags,
+    ) -> Result<RawContext<R>, Error> {
+        let mut alloc = *alloc;
+        let mut ctx = RawContext::from_raw(sys::spng_ctx_new2(&mut alloc, flags.bits() as _))?;
+        if let Some(free_fn) = alloc.free_fn {
+            ctx.free_fn = free_fn;
+        }
+        Ok(ctx)
+    }
+
+    unsafe fn from_raw(raw: *mut sys::spng_ctx) -> Result<RawContext<R>, Error> {
+        if raw.is_null() {
+            Err(Error::Mem)
+        } else {
+            Ok(RawContext {
+                raw,
+                stream: None,
+                image_sizes: Cell::new([None; FORMAT_COUNT]),
+                free_fn: libc::free,
+                text: Vec::new(),
+                #[cfg(test)]
+                image_size_queries: Cell::new(0),
+            })
+        }
+    }
+
+    /// Boxes the stream and installs `rw_fn` as its callback.
+    ///
+    /// Safety: `rw_fn` must expect a `Stream<R>` user pointer.
+    #[cfg(feature = "std")]
+    unsafe fn set_stream(&mut self, inner: R, rw_fn: sys::spng_rw_fn) -> Result<(), Error> {
+        self.set_boxed(inner, |raw, stream| {
+            sys::spng_set_png_stream(raw, rw_fn, stream as *mut _)
+        })
+    }
+
+    /// Boxes `inner` and hands the box's pointer to libspng with `set`.
+    ///
+    /// The user pointer handed to libspng and the stored pointer share the provenance of the
+    /// `Box::into_raw` pointer, so no `&mut` to the stream is held while libspng may access it.
+    /// The box is only stored once libspng accepts it.
+    unsafe fn set_boxed<F>(&mut self, inner: R, set: F) -> Result<(), Error>
+    where
+        F: FnOnce(*mut sys::spng_ctx, *mut Stream<R>) -> libc::c_int,
+    {
+        let stream = NonNull::from(Box::leak(Box::new(Stream::new(inner))));
+        let ret = set(self.raw, stream.as_ptr());
+        match check_err(ret) {
+            Ok(()) => {
+                self.stream = Some(stream);
+                self.image_sizes.take();
+                Ok(())
+            }
+            Err(error) => {
+                drop(Box::from_raw(stream.as_ptr()));
+                Err(error)
+            }
+        }
+    }
+
+    /// Limits the number of bytes the input stream delivers to libspng, reading past `max`
+    /// fails with `Error::LimitsExceeded`. Does nothing if no stream was set.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_max_input_bytes(&mut self, max: u64) {
+        if let Some(stream) = self.stream {
+            unsafe { (*stream.as_ptr()).max_read = max }
+        }
+    }
+
+    /// Frees the context and returns the stream, if one was set.
+    #[cfg(feature = "std")]
+    pub(crate) fn into_stream(mut self) -> Option<R> {
         unsafe {
-            let raw = sys::spng_ctx_new(flags.bits() as _);
-            if raw.is_null() {
-                Err(Error::Mem)
-            } else {
-                Ok(RawContext { raw, reader: None })
+            sys::spng_ctx_free(self.raw);
+            self.raw = ptr::null_mut();
+            self.stream
+                .take()
+                .map(|stream| Box::from_raw(stream.as_ptr()).inner)
+        }
+    }
+
+    /// Returns the stream, if one was set. libspng isn't running while `self` is borrowed
+    /// mutably, so the stream may be read from directly.
+    #[cfg(feature = "std")]
+    pub(crate) fn stream_mut(&mut self) -> Option<&mut R> {
+        self.stream
+            .map(|stream| unsafe { &mut (*stream.as_ptr()).inner })
+    }
+
+    /// Converts a libspng return code, replacing `Error::IoError` with the stream's
+    /// captured error if there is one.
+    fn check(&self, ret: i32) -> Result<(), Error> {
+        match check_err(ret) {
+            #[cfg(feature = "std")]
+            Err(Error::IoError) => {
+                let stream = self.stream.map(|stream| unsafe { &mut *stream.as_ptr() });
+                let (error, payload) = match stream {
+                    Some(stream) => (stream.error.take(), stream.panic.take()),
+                    None => (None, None),
+                };
+                if let Some(payload) = payload {
+                    panic::resume_unwind(payload);
+                }
+                Err(error.unwrap_or(Error::IoError))
             }
+            result => result,
         }
     }
 
     /// Set how chunk CRC errors should be handled for critical and ancillary chunks.
+    ///
+    /// This only applies to decoding. The encoder always computes chunk CRCs, and libspng returns
+    /// `Error::CtxType` for an encoder context.
     pub fn set_crc_action(
         &mut self,
         critical: CrcAction,
         ancillary: CrcAction,
     ) -> Result<(), Error> {
         unsafe {
-            check_err(sys::spng_set_crc_action(
+            self.check(sys::spng_set_crc_action(
                 self.raw,
                 critical as i32,
                 ancillary as i32,
@@ -128,7 +377,7 @@ impl<R> RawContext<R> {
         let mut width = 0;
         let mut height = 0;
         unsafe {
-            check_err(sys::spng_get_image_limits(
+            self.check(sys::spng_get_image_limits(
                 self.raw,
                 &mut width,
                 &mut height,
@@ -139,7 +388,7 @@ impl<R> RawContext<R> {
 
     /// Set image width and height limits, these may not be larger than `(2^31)-1`.
     pub fn set_image_limits(&mut self, max_width: u32, max_height: u32) -> Result<(), Error> {
-        unsafe { check_err(sys::spng_set_image_limits(self.raw, max_width, max_height)) }
+        unsafe { self.check(sys::spng_set_image_limits(self.raw, max_width, max_height)) }
     }
 
     /// Get chunk size and chunk cache limits.
@@ -149,7 +398,7 @@ impl<R> RawContext<R> {
         let mut chunk_size = 0;
         let mut cache_size = 0;
         unsafe {
-            check_err(sys::spng_get_chunk_limits(
+            self.check(sys::spng_get_chunk_limits(
                 self.raw,
                 &mut chunk_size,
                 &mut cache_size,
@@ -161,23 +410,49 @@ impl<R> RawContext<R> {
     /// Set chunk size and chunk cache limits, the default chunk size limit is `(2^31)-1`, the default
     /// chunk cache limit is `SIZE_MAX`.
     pub fn set_chunk_limits(&mut self, chunk_size: usize, cache_size: usize) -> Result<(), Error> {
-        unsafe { check_err(sys::spng_set_chunk_limits(self.raw, chunk_size, cache_size)) }
+        unsafe { self.check(sys::spng_set_chunk_limits(self.raw, chunk_size, cache_size)) }
+    }
+
+    /// Get chunk size and chunk cache limits, with `None` for unlimited.
+    pub fn get_chunk_limits_typed(&self) -> Result<ChunkLimits, Error> {
+        let (chunk_size, cache_size) = self.get_chunk_limits()?;
+        Ok(ChunkLimits {
+            chunk_size: Some(chunk_size).filter(|&size| size != CHUNK_SIZE_MAX),
+            cache_size: Some(cache_size).filter(|&size| size != usize::MAX),
+        })
+    }
+
+    /// Set chunk size and chunk cache limits, with `None` for unlimited.
+    ///
+    /// An unlimited chunk size is the PNG maximum of `(2^31)-1`, an unlimited cache size is
+    /// `SIZE_MAX`. libspng returns `Error::Inval` if the chunk size limit is larger than
+    /// `(2^31)-1` or the cache size limit.
+    pub fn set_chunk_limits_typed(&mut self, limits: ChunkLimits) -> Result<(), Error> {
+        self.set_chunk_limits(
+            limits.chunk_size.unwrap_or(CHUNK_SIZE_MAX),
+            limits.cache_size.unwrap_or(usize::MAX),
+        )
     }
 
     /// Get the image header.
     pub fn get_ihdr(&self) -> Result<Ihdr, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_ihdr(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_ihdr(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
 
+    /// Get the image header with typed accessors.
+    pub fn get_image_header(&self) -> Result<ImageHeader, Error> {
+        self.get_ihdr().map(ImageHeader)
+    }
+
     /// Get the image palette.
     pub fn get_plte(&self) -> Result<Ref<Plte>, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_plte(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_plte(self.raw, chunk.as_mut_ptr()))?;
             Ok(Ref::from(Plte(chunk.assume_init())))
         }
     }
@@ -186,7 +461,7 @@ impl<R> RawContext<R> {
     pub fn get_trns(&self) -> Result<Trns, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_trns(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_trns(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
@@ -195,7 +470,7 @@ impl<R> RawContext<R> {
     pub fn get_chrm(&self) -> Result<Chrm, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_chrm(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_chrm(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
@@ -204,7 +479,7 @@ impl<R> RawContext<R> {
     pub fn get_chrm_int(&self) -> Result<ChrmInt, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_chrm_int(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_chrm_int(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
@@ -213,7 +488,7 @@ impl<R> RawContext<R> {
     pub fn get_gama(&self) -> Result<f64, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_gama(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_gama(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
@@ -222,11 +497,36 @@ impl<R> RawContext<R> {
     pub fn get_gama_int(&self) -> Result<u32, Error> {
         let mut gama_int = 0;
         unsafe {
-            check_err(sys::spng_get_gama_int(self.raw, &mut gama_int))?;
+            self.check(sys::spng_get_gama_int(self.raw, &mut gama_int))?;
         }
         Ok(gama_int)
     }
 
+    /// Returns `true` if the image has a chunk of type `chunk`.
+    ///
+    /// This calls the chunk's getter and maps `Error::Chunkavail` to `false`. Chunks stored after
+    /// the image data are only available once the image has been decoded.
+    pub fn has_chunk(&self, chunk: ChunkType) -> Result<bool, Error> {
+        let has_chunk = match chunk {
+            ChunkType::Plte => self.get_plte().map(drop),
+            ChunkType::Trns => self.get_trns().map(drop),
+            ChunkType::Chrm => self.get_chrm_int().map(drop),
+            ChunkType::Gama => self.get_gama_int().map(drop),
+            ChunkType::Iccp => self.get_iccp().map(drop),
+            ChunkType::Sbit => self.get_sbit().map(drop),
+            ChunkType::Srgb => self.get_srgb().map(drop),
+            ChunkType::Text => self.get_text().map(drop),
+            ChunkType::Bkgd => self.get_bkgd().map(drop),
+            ChunkType::Hist => self.get_hist().map(drop),
+            ChunkType::Phys => self.get_phys().map(drop),
+            ChunkType::Splt => self.get_splt().map(drop),
+            ChunkType::Time => self.get_time().map(drop),
+            ChunkType::Offs => self.get_offs().map(drop),
+            ChunkType::Exif => self.get_exif().map(drop),
+        };
+        Ok(has_chunk.chunk_avail()?.is_some())
+    }
+
     /// Get the ICC profile.
     ///
     /// ### Note
@@ -234,7 +534,7 @@ impl<R> RawContext<R> {
     pub fn get_iccp(&self) -> Result<Ref<Iccp>, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_iccp(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_iccp(self.raw, chunk.as_mut_ptr()))?;
             let chunk: Iccp = mem::transmute(chunk.assume_init());
             Ok(Ref::from(chunk))
         }
@@ -244,7 +544,7 @@ impl<R> RawContext<R> {
     pub fn get_sbit(&self) -> Result<Sbit, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_sbit(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_sbit(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
@@ -253,37 +553,70 @@ impl<R> RawContext<R> {
     pub fn get_srgb(&self) -> Result<u8, Error> {
         unsafe {
             let mut rendering_intent = 0;
-            check_err(sys::spng_get_srgb(self.raw, &mut rendering_intent))?;
+            self.check(sys::spng_get_srgb(self.raw, &mut rendering_intent))?;
             Ok(rendering_intent)
         }
     }
 
+    /// Get the `sRGB` rendering intent as a [`RenderingIntent`].
+    pub fn get_srgb_intent(&self) -> Result<RenderingIntent, Error> {
+        RenderingIntent::try_from(self.get_srgb()?)
+    }
+
     /// Get text information.
     ///
+    /// A `tEXt`, `zTXt` or `iTXt` chunk with a CRC mismatch is skipped and the other text chunks
+    /// are returned, as [`CrcAction::Discard`] is the default for ancillary chunks. libspng reads
+    /// and decompresses all text chunks together and doesn't expose them individually, so any
+    /// other error in a text chunk fails the whole call.
+    ///
     /// ### Note
     /// Due to the structure of PNG files it is recommended to call this function after [`decode_image`].
     ///
     /// [`decode_image`]: method@RawContext::decode_image
     pub fn get_text(&self) -> Result<Ref<Vec<Text>>, Error> {
+        let mut buffer = TextBuffer::new();
+        self.fill_text(&mut buffer)?;
+        let vec: Vec<Text> = buffer.text.into_iter().map(Text).collect();
+        Ok(Ref::from(vec))
+    }
+
+    /// Get text information into a reusable `buffer`, avoiding an allocation per call once the
+    /// buffer is large enough.
+    ///
+    /// The returned entries borrow both the context and the buffer. The buffer can be reused
+    /// with any other context afterwards.
+    pub fn get_text_into<'a>(&'a self, buffer: &'a mut TextBuffer) -> Result<&'a [Text], Error> {
+        self.fill_text(buffer)?;
+        let text = buffer.text.as_ptr() as *const Text;
+        // `Text` is `repr(transparent)` over `spng_text`
+        unsafe { Ok(slice::from_raw_parts(text, buffer.text.len())) }
+    }
+
+    fn fill_text(&self, buffer: &mut TextBuffer) -> Result<(), Error> {
         unsafe {
-            use std::ptr;
             let mut len = 0;
-            check_err(sys::spng_get_text(self.raw, ptr::null_mut(), &mut len))?;
-            let mut vec = Vec::<Text>::new();
-            vec.reserve_exact(len as usize);
-            vec.set_len(len as usize);
-            let text_ptr = vec.as_mut_ptr() as *mut sys::spng_text;
-            check_err(sys::spng_get_text(self.raw, text_ptr, &mut len))?;
-            Ok(Ref::from(vec))
+            self.check(sys::spng_get_text(self.raw, ptr::null_mut(), &mut len))?;
+            buffer.text.clear();
+            buffer.text.resize(len as usize, mem::zeroed());
+            self.check(sys::spng_get_text(
+                self.raw,
+                buffer.text.as_mut_ptr(),
+                &mut len,
+            ))
         }
     }
 
     /// Get the image background color.
     pub fn get_bkgd(&self) -> Result<Bkgd, Error> {
+        let color_type = ColorType::try_from(self.get_ihdr()?.color_type)?;
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_bkgd(self.raw, chunk.as_mut_ptr()))?;
-            Ok(chunk.assume_init())
+            self.check(sys::spng_get_bkgd(self.raw, chunk.as_mut_ptr()))?;
+            Ok(Bkgd {
+                bkgd: chunk.assume_init(),
+                color_type,
+            })
         }
     }
 
@@ -291,7 +624,7 @@ impl<R> RawContext<R> {
     pub fn get_hist(&self) -> Result<Hist, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_hist(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_hist(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
@@ -300,24 +633,16 @@ impl<R> RawContext<R> {
     pub fn get_phys(&self) -> Result<Phys, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_phys(self.raw, chunk.as_mut_ptr()))?;
-            Ok(chunk.assume_init())
+            self.check(sys::spng_get_phys(self.raw, chunk.as_mut_ptr()))?;
+            Ok(Phys(chunk.assume_init()))
         }
     }
 
     /// Get the suggested palettes.
     pub fn get_splt(&self) -> Result<Ref<Vec<Splt>>, Error> {
-        unsafe {
-            use std::ptr;
-            let mut len = 0;
-            check_err(sys::spng_get_splt(self.raw, ptr::null_mut(), &mut len))?;
-            let mut vec = Vec::<Splt>::new();
-            vec.reserve_exact(len as usize);
-            vec.set_len(len as usize);
-            let splt_ptr = vec.as_mut_ptr() as *mut sys::spng_splt;
-            check_err(sys::spng_get_splt(self.raw, splt_ptr, &mut len))?;
-            Ok(Ref::from(vec))
-        }
+        let splt =
+            unsafe { read_array(|splt, len| self.check(sys::spng_get_splt(self.raw, splt, len)))? };
+        Ok(Ref::from(splt.into_iter().map(Splt).collect::<Vec<_>>()))
     }
 
     /// Get the modification time.
@@ -329,16 +654,38 @@ impl<R> RawContext<R> {
     pub fn get_time(&self) -> Result<Time, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_time(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_time(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
 
+    /// Get the modification time as a `chrono::NaiveDateTime`.
+    ///
+    /// Returns `Error::Time` if the fields do not form a valid date and time. A leap second
+    /// (`second == 60`) is represented as `59` seconds plus one second of nanoseconds.
+    ///
+    /// See [`get_time`] for when this should be called.
+    ///
+    /// [`get_time`]: method@RawContext::get_time
+    #[cfg(feature = "chrono")]
+    pub fn get_time_chrono(&self) -> Result<chrono::NaiveDateTime, Error> {
+        let time = self.get_time()?;
+        let (second, nano) = match time.second {
+            60 => (59, 1_000_000_000),
+            second => (second as u32, 0),
+        };
+        chrono::NaiveDate::from_ymd_opt(time.year as i32, time.month as u32, time.day as u32)
+            .and_then(|date| {
+                date.and_hms_nano_opt(time.hour as u32, time.minute as u32, second, nano)
+            })
+            .ok_or(Error::Time)
+    }
+
     /// Get the image offset.
     pub fn get_offs(&self) -> Result<Offs, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_offs(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_offs(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
@@ -352,17 +699,22 @@ impl<R> RawContext<R> {
     pub fn get_exif(&self) -> Result<Ref<Exif>, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_exif(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_exif(self.raw, chunk.as_mut_ptr()))?;
             let chunk: Exif = mem::transmute(chunk.assume_init());
             Ok(Ref::from(chunk))
         }
     }
 
     /// Get the current, to-be-decoded row's information.
+    ///
+    /// Returns `Error::Oi` once the last row has been decoded, e.g. after [`decode_image`]
+    /// without the `SPNG_DECODE_PROGRESSIVE` flag.
+    ///
+    /// [`decode_image`]: method@RawContext::decode_image
     pub fn get_row_info(&self) -> Result<RowInfo, Error> {
         unsafe {
             let mut chunk = MaybeUninit::uninit();
-            check_err(sys::spng_get_row_info(self.raw, chunk.as_mut_ptr()))?;
+            self.check(sys::spng_get_row_info(self.raw, chunk.as_mut_ptr()))?;
             Ok(chunk.assume_init())
         }
     }
@@ -374,38 +726,69 @@ impl<R> RawContext<R> {
     ///
     /// [`decode_image`]: method@RawContext::decode_image
     pub fn get_unknown_chunks(&self) -> Result<Ref<Vec<UnknownChunk>>, Error> {
-        unsafe {
-            use std::ptr;
-            let mut len = 0;
-            check_err(sys::spng_get_unknown_chunks(
-                self.raw,
-                ptr::null_mut(),
-                &mut len,
-            ))?;
-            let mut vec = Vec::<UnknownChunk>::new();
-            vec.reserve_exact(len as usize);
-            vec.set_len(len as usize);
-            let chunk_ptr = vec.as_mut_ptr() as *mut sys::spng_unknown_chunk;
-            check_err(sys::spng_get_unknown_chunks(self.raw, chunk_ptr, &mut len))?;
-            Ok(Ref::from(vec))
+        let chunks = unsafe {
+            read_array(|chunks, len| {
+                self.check(sys::spng_get_unknown_chunks(self.raw, chunks, len))
+            })?
+        };
+        Ok(Ref::from(
+            chunks.into_iter().map(UnknownChunk).collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Returns owned copies of the unknown chunks.
+    ///
+    /// Unlike [`get_unknown_chunks`], the returned chunks don't borrow from the context.
+    ///
+    /// [`get_unknown_chunks`]: method@RawContext::get_unknown_chunks
+    pub fn get_unknown_chunks_owned(&self) -> Result<Vec<OwnedUnknownChunk>, Error> {
+        let chunks = self.get_unknown_chunks()?;
+        Ok(chunks.iter().map(OwnedUnknownChunk::from).collect())
+    }
+
+    /// Returns the format [`Format::Auto`] selects for the image, see [`Format::for_image`].
+    /// Other formats are returned as-is.
+    ///
+    /// The chunks preceding the image data are read to find a `tRNS` chunk.
+    pub fn resolve_format(&self, format: Format) -> Result<Format, Error> {
+        if format != Format::Auto {
+            return Ok(format);
         }
+        let ihdr = self.get_ihdr()?;
+        let color_type = ColorType::try_from(ihdr.color_type)?;
+        let bit_depth = BitDepth::try_from(ihdr.bit_depth)?;
+        let trns = self.has_chunk(ChunkType::Trns)?;
+        Ok(Format::for_image(color_type, bit_depth, trns))
     }
 
     /// Calculates decoded image buffer size for the given output format.
     ///
-    /// PNG data must have been set prior with [`set_png_stream`] or [`set_png_buffer`].
+    /// PNG data must have been set prior with [`set_png_stream_reader`] or [`set_png_buffer`].
+    ///
+    /// The size is cached per format until the input or the image header changes.
     ///
-    /// [`set_png_stream`]: method@RawContext::set_png_stream
+    /// [`set_png_stream_reader`]: method@RawContext::set_png_stream_reader
     /// [`set_png_buffer`]: method@RawContext::set_png_buffer
     pub fn decoded_image_size(&self, out_format: Format) -> Result<usize, Error> {
+        let out_format = self.resolve_format(out_format)?;
+        let slot = format_slot(out_format);
+        let mut image_sizes = self.image_sizes.get();
+        if let Some(len) = image_sizes[slot] {
+            return Ok(len);
+        }
         let mut len = 0;
         unsafe {
-            check_err(sys::spng_decoded_image_size(
+            self.check(sys::spng_decoded_image_size(
                 self.raw,
                 out_format as _,
                 &mut len,
             ))?;
         }
+        #[cfg(test)]
+        self.image_size_queries
+            .set(self.image_size_queries.get() + 1);
+        image_sizes[slot] = Some(len);
+        self.image_sizes.set(image_sizes);
         Ok(len)
     }
 
@@ -424,16 +807,24 @@ impl<R> RawContext<R> {
     ///
     /// The function may only be called **once** per context.
     ///
+    /// Without the `SPNG_DECODE_PROGRESSIVE` flag this is a single blocking call. A `RawContext`
+    /// is neither `Send` nor `Sync` and this takes `&mut self`, so the decode can't be observed,
+    /// e.g. with [`get_row_info`], until it returns. To report progress decode progressively
+    /// with [`decode_row`] or [`Reader::rows`](crate::Reader::rows).
+    ///
     /// [`decode_image`]: method@RawContext::decode_image
     /// [`decoded_image_size`]: method@RawContext::decoded_image_size
+    /// [`get_row_info`]: method@RawContext::get_row_info
+    /// [`decode_row`]: method@RawContext::decode_row
     pub fn decode_image(
         &mut self,
         out: &mut [u8],
         out_format: Format,
         flags: DecodeFlags,
     ) -> Result<(), Error> {
+        let out_format = self.resolve_format(out_format)?;
         unsafe {
-            check_err(sys::spng_decode_image(
+            self.check(sys::spng_decode_image(
                 self.raw,
                 out.as_mut_ptr() as _,
                 out.len(),
@@ -458,7 +849,7 @@ impl<R> RawContext<R> {
     /// [`decode_scanline`]: method@RawContext::decode_scanline
     pub fn decode_row(&mut self, out: &mut [u8]) -> Result<(), Error> {
         unsafe {
-            check_err(sys::spng_decode_row(
+            self.check(sys::spng_decode_row(
                 self.raw,
                 out.as_mut_ptr() as _,
                 out.len(),
@@ -475,7 +866,7 @@ impl<R> RawContext<R> {
     ///
     /// [`decode_image`]: method@RawContext::decode_image
     pub fn decode_chunks(&mut self) -> Result<(), Error> {
-        unsafe { check_err(sys::spng_decode_chunks(self.raw)) }
+        unsafe { self.check(sys::spng_decode_chunks(self.raw)) }
     }
 
     /// Decodes a scanline to `out`.
@@ -490,7 +881,7 @@ impl<R> RawContext<R> {
     /// [`decode_image`]: method@RawContext::decode_image
     pub fn decode_scanline(&mut self, output: &mut [u8]) -> Result<(), Error> {
         unsafe {
-            check_err(sys::spng_decode_scanline(
+            self.check(sys::spng_decode_scanline(
                 self.raw,
                 output.as_mut_ptr() as _,
                 output.len(),
@@ -498,37 +889,288 @@ impl<R> RawContext<R> {
         }
     }
 
+    /// Decodes the image and discards the pixels, returning the first decoding error if any.
+    ///
+    /// The image is decoded progressively into a single scanline buffer, so memory use is bounded
+    /// by the widest row rather than the whole image. Chunks after the image data are decoded
+    /// up to the end-of-file (IEND) marker.
+    ///
+    /// This consumes the context's single call to [`decode_image`].
+    ///
+    /// [`decode_image`]: method@RawContext::decode_image
+    pub fn validate(&mut self) -> Result<(), Error> {
+        let ihdr = self.get_ihdr()?;
+        let image_size = self.decoded_image_size(Format::Raw)?;
+        let mut row = vec![0; image_size / ihdr.height as usize];
+        self.decode_image(&mut [], Format::Raw, DecodeFlags::PROGRESSIVE)?;
+        loop {
+            match self.decode_row(&mut row) {
+                Ok(()) => continue,
+                Err(Error::Oi) => break,
+                Err(error) => return Err(error),
+            }
+        }
+        self.decode_chunks()
+    }
+
+    /// Set the image header.
+    ///
+    /// The context must have been created with [`ContextFlags::ENCODER`].
+    ///
+    /// Returns `Error::CompressionMethod`, `Error::FilterMethod` or `Error::InterlaceMethod`
+    /// without calling libspng if a method isn't defined by the `png` specification.
+    pub fn set_ihdr(&mut self, ihdr: &Ihdr) -> Result<(), Error> {
+        if ihdr.compression_method != 0 {
+            return Err(Error::CompressionMethod);
+        }
+        if ihdr.filter_method != 0 {
+            return Err(Error::FilterMethod);
+        }
+        InterlaceMethod::try_from(ihdr.interlace_method)?;
+        let mut ihdr = *ihdr;
+        self.image_sizes.take();
+        unsafe { self.check(sys::spng_set_ihdr(self.raw, &mut ihdr)) }
+    }
+
+    /// Set the palette.
+    ///
+    /// The palette must be set before the [`set_trns`] and [`set_bkgd`] chunks of indexed images.
+    ///
+    /// [`set_trns`]: method@RawContext::set_trns
+    /// [`set_bkgd`]: method@RawContext::set_bkgd
+    pub fn set_plte(&mut self, plte: &Plte) -> Result<(), Error> {
+        let mut plte = plte.0;
+        unsafe { self.check(sys::spng_set_plte(self.raw, &mut plte)) }
+    }
+
+    /// Set the transparency.
+    pub fn set_trns(&mut self, trns: &Trns) -> Result<(), Error> {
+        let mut trns = *trns;
+        unsafe { self.check(sys::spng_set_trns(self.raw, &mut trns)) }
+    }
+
+    /// Set the image gamma.
+    pub fn set_gama(&mut self, gamma: f64) -> Result<(), Error> {
+        unsafe { self.check(sys::spng_set_gama(self.raw, gamma)) }
+    }
+
+    /// Set the background color.
+    pub fn set_bkgd(&mut self, bkgd: &Bkgd) -> Result<(), Error> {
+        let mut bkgd = bkgd.bkgd;
+        unsafe { self.check(sys::spng_set_bkgd(self.raw, &mut bkgd)) }
+    }
+
+    /// Set the physical pixel dimensions.
+    pub fn set_phys(&mut self, phys: &Phys) -> Result<(), Error> {
+        let mut phys = phys.0;
+        unsafe { self.check(sys::spng_set_phys(self.raw, &mut phys)) }
+    }
+
+    /// Set `tEXt` chunks from `(keyword, text)` pairs, replacing any text set before.
+    ///
+    /// `tEXt` is Latin-1, so characters above `U+00FF` and null characters return `Error::Text`,
+    /// or `Error::TextKeyword` in a keyword. Keywords must be 1 to 79 characters and the text
+    /// must not be empty. The text is kept by the context until it is dropped, as libspng
+    /// doesn't copy it.
+    pub fn set_text(&mut self, text: &[(&str, &str)]) -> Result<(), Error> {
+        let latin1 = |s: &str, error: Error| {
+            s.chars()
+                .map(|c| match c as u32 {
+                    1..=0xff => Ok(c as u8),
+                    _ => Err(error.clone()),
+                })
+                .collect::<Result<Vec<u8>, Error>>()
+        };
+        let mut values = Vec::with_capacity(text.len());
+        let mut chunks = Vec::with_capacity(text.len());
+        for &(keyword, value) in text {
+            let keyword = latin1(keyword, Error::TextKeyword)?;
+            let mut value = latin1(value, Error::Text)?;
+            let length = value.len();
+            value.push(0);
+            // SAFETY: `spng_text` is plain data, null pointers are unused for `tEXt`
+            let mut chunk: sys::spng_text = unsafe { mem::zeroed() };
+            // The last byte is left as the null terminator
+            let keyword_storage = chunk.keyword.len() - 1;
+            if keyword.is_empty() || keyword.len() > keyword_storage {
+                return Err(Error::TextKeyword);
+            }
+            for (dst, &src) in chunk.keyword.iter_mut().zip(&keyword) {
+                *dst = src as _;
+            }
+            chunk.type_ = TextType::Text as _;
+            chunk.length = length;
+            // The heap buffer doesn't move when `value` is moved into `values`
+            chunk.text = value.as_mut_ptr() as _;
+            values.push(value);
+            chunks.push(chunk);
+        }
+        unsafe {
+            self.check(sys::spng_set_text(
+                self.raw,
+                chunks.as_mut_ptr(),
+                chunks.len() as u32,
+            ))?;
+        }
+        self.text = values;
+        Ok(())
+    }
+
+    /// Set the modification time.
+    pub fn set_time(&mut self, time: &Time) -> Result<(), Error> {
+        let mut time = *time;
+        unsafe { self.check(sys::spng_set_time(self.raw, &mut time)) }
+    }
+
+    /// Encodes the image from `img` in the source format `fmt`. Only `Format::Png` (host-endian)
+    /// and `Format::Raw` (big-endian) are supported.
+    ///
+    /// The `img` buffer must be exactly the size of the image described by the header set with
+    /// [`set_ihdr`].
+    ///
+    /// If the `SPNG_ENCODE_PROGRESSIVE` flag is set, the context will be initialized for
+    /// progressive encoding with [`encode_row`] and the `img` buffer is ignored.
+    ///
+    /// If the `SPNG_ENCODE_FINALIZE` flag is set, the end-of-file (IEND) marker is written after
+    /// the image data.
+    ///
+    /// [`set_ihdr`]: method@RawContext::set_ihdr
+    /// [`encode_row`]: method@RawContext::encode_row
+    pub fn encode_image(
+        &mut self,
+        img: &[u8],
+        fmt: Format,
+        flags: EncodeFlags,
+    ) -> Result<(), Error> {
+        unsafe {
+            self.check(sys::spng_encode_image(
+                self.raw,
+                img.as_ptr() as _,
+                img.len(),
+                fmt as _,
+                flags.bits() as _,
+            ))
+        }
+    }
+
+    /// Encodes a row of the image, interlacing it if needed.
+    ///
+    /// This function requires the encoder to be initialized by calling [`encode_image`] with the
+    /// `SPNG_ENCODE_PROGRESSIVE` flag set.
+    ///
+    /// For the last row and subsequent calls the return value is `SPNG_EOI`.
+    ///
+    /// [`encode_image`]: method@RawContext::encode_image
+    pub fn encode_row(&mut self, row: &[u8]) -> Result<(), Error> {
+        unsafe { self.check(sys::spng_encode_row(self.raw, row.as_ptr() as _, row.len())) }
+    }
+
+    /// Encodes all chunks before or after the image data (IDAT) stream,
+    /// depending on the state of the encoder.
+    ///
+    /// If the image is encoded this function will write the end-of-file (IEND) marker.
+    pub fn encode_chunks(&mut self) -> Result<(), Error> {
+        unsafe { self.check(sys::spng_encode_chunks(self.raw)) }
+    }
+
     /// Sets `option` to the specified `value`.
     pub fn set_option(&mut self, option: SpngOption, value: i32) -> Result<(), Error> {
-        unsafe { check_err(sys::spng_set_option(self.raw, option as _, value as _)) }
+        unsafe { self.check(sys::spng_set_option(self.raw, option as _, value as _)) }
+    }
+
+    /// Returns the encoded `png` when encoding with `SpngOption::EncodeToBuffer` set.
+    ///
+    /// The buffer owned by libspng is copied and freed, so this can only be called once the image
+    /// is encoded and only succeeds once.
+    pub fn take_png_buffer(&mut self) -> Result<Vec<u8>, Error> {
+        let mut len = 0;
+        let mut error = 0;
+        unsafe {
+            let buf = sys::spng_get_png_buffer(self.raw, &mut len, &mut error);
+            self.check(error)?;
+            if buf.is_null() {
+                return Err(Error::Internal);
+            }
+            let png = slice::from_raw_parts(buf as *const u8, len).to_vec();
+            (self.free_fn)(buf);
+            Ok(png)
+        }
     }
 
     /// Gets the value for the specified `option`.
     pub fn get_option(&self, option: SpngOption) -> Result<i32, Error> {
         let mut value = 0;
         unsafe {
-            check_err(sys::spng_get_option(self.raw, option as _, &mut value))?;
+            self.check(sys::spng_get_option(self.raw, option as _, &mut value))?;
         }
         Ok(value as _)
     }
 }
 
+#[cfg(feature = "std")]
 impl<R: io::Read> RawContext<R> {
     /// Set the input `png` stream reader. The input buffer or stream may only be set once per context.
+    pub fn set_png_stream_reader(&mut self, reader: R) -> Result<(), Error> {
+        unsafe { self.set_stream(reader, Some(read_fn::<R>)) }
+    }
+
+    /// Set the input `png` stream reader.
+    #[deprecated(since = "0.2.0", note = "use `set_png_stream_reader`")]
     pub fn set_png_stream(&mut self, reader: R) -> Result<(), Error> {
-        let boxed = Box::new(reader);
-        let unboxed = Box::into_raw(boxed);
-        self.reader = NonNull::new(unboxed);
-        let rw_fn: sys::spng_rw_fn = Some(read_fn::<R>);
-        unsafe { check_err(sys::spng_set_png_stream(self.raw, rw_fn, unboxed as *mut _)) }
+        self.set_png_stream_reader(reader)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> RawContext<W> {
+    /// Set the output `png` stream writer. The output stream may only be set once per context.
+    ///
+    /// Short writes are retried until libspng's buffer is fully written, as are writes that fail
+    /// with `ErrorKind::Interrupted`. The writer is not flushed, see [`Writer::finish`].
+    ///
+    /// [`Writer::finish`]: crate::Writer::finish
+    pub fn set_png_stream_writer(&mut self, writer: W) -> Result<(), Error> {
+        unsafe { self.set_stream(writer, Some(write_fn::<W>)) }
+    }
+}
+
+/// Calls a libspng getter that fills an array, first with a null array to get the number of
+/// entries and then with an array of that capacity.
+///
+/// The entries are only treated as initialized once `get` succeeds, and no more than it reports.
+///
+/// Safety: `get` must write the number of entries it initialized to its second argument.
+unsafe fn read_array<T, F>(mut get: F) -> Result<Vec<T>, Error>
+where
+    F: FnMut(*mut T, &mut u32) -> Result<(), Error>,
+{
+    let mut len = 0;
+    get(ptr::null_mut(), &mut len)?;
+    let mut vec = Vec::with_capacity(len as usize);
+    get(vec.as_mut_ptr(), &mut len)?;
+    vec.set_len((len as usize).min(vec.capacity()));
+    Ok(vec)
+}
+
+/// Fails fast with `Error::Signature` on a buffer that can't be a `png`.
+fn check_signature(buf: &[u8]) -> Result<(), Error> {
+    if crate::is_png(buf) {
+        Ok(())
+    } else {
+        Err(Error::Signature)
     }
 }
 
 impl<'a> RawContext<&'a [u8]> {
     /// Set the input `png` buffer. The input buffer or stream may only be set once per context.
+    ///
+    /// Returns `Error::Signature` without involving libspng if `buf` doesn't start with the
+    /// `png` signature.
     pub fn set_png_buffer(&mut self, buf: &'a [u8]) -> Result<(), Error> {
+        check_signature(buf)?;
+        self.image_sizes.take();
         unsafe {
-            check_err(sys::spng_set_png_buffer(
+            self.check(sys::spng_set_png_buffer(
                 self.raw,
                 buf.as_ptr() as *const _,
                 buf.len(),
@@ -537,13 +1179,80 @@ impl<'a> RawContext<&'a [u8]> {
     }
 }
 
+impl<B: Deref<Target = [u8]>> RawContext<B> {
+    /// Set the input `png` buffer, taking ownership of it. The input buffer or stream may only be
+    /// set once per context.
+    ///
+    /// Unlike [`set_png_buffer`] the context can be stored without borrowing the data, e.g. a
+    /// `Vec<u8>` or a memory-mapped file:
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let file = std::fs::File::open("image.png")?;
+    /// let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    /// let mut ctx = spng::raw::RawContext::new()?;
+    /// ctx.set_png_owned_buffer(mmap)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The buffer is dereferenced once and must not change while the context is alive.
+    /// Like [`set_png_buffer`], `Error::Signature` is returned if the buffer doesn't start with
+    /// the `png` signature.
+    ///
+    /// [`set_png_buffer`]: method@RawContext::set_png_buffer
+    pub fn set_png_owned_buffer(&mut self, buf: B) -> Result<(), Error> {
+        check_signature(&buf)?;
+        unsafe {
+            self.set_boxed(buf, |raw, stream| {
+                let buf: &[u8] = &(*stream).inner;
+                sys::spng_set_png_buffer(raw, buf.as_ptr() as *const _, buf.len())
+            })
+        }
+    }
+}
+
+/// The largest chunk size allowed by the PNG specification, `(2^31)-1`
+const CHUNK_SIZE_MAX: usize = i32::MAX as usize;
+
+/// Chunk size and chunk cache limits for [`RawContext::set_chunk_limits_typed`]
+///
+/// The default is unlimited.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ChunkLimits {
+    /// Maximum size of a single chunk, `None` for the PNG maximum of `(2^31)-1`
+    pub chunk_size: Option<usize>,
+    /// Maximum memory used to cache chunk data, `None` for unlimited
+    pub cache_size: Option<usize>,
+}
+
+/// Reusable storage for [`RawContext::get_text_into`]
+#[derive(Default)]
+pub struct TextBuffer {
+    text: Vec<sys::spng_text>,
+}
+
+impl TextBuffer {
+    pub fn new() -> TextBuffer {
+        TextBuffer::default()
+    }
+}
+
+impl fmt::Debug for TextBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TextBuffer")
+            .field("capacity", &self.text.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
 /// Attaches lifetime `'a` to `T`.
 pub struct Ref<'a, T: 'a> {
     data: T,
     _p: PhantomData<&'a ()>,
 }
 
-impl<'a, T: 'a> std::ops::Deref for Ref<'a, T> {
+impl<'a, T: 'a> Deref for Ref<'a, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         &self.data
@@ -561,15 +1270,18 @@ impl<'a, T: 'a> From<T> for Ref<'a, T> {
 
 /// `PNG` chunk data
 pub mod chunk {
+    use crate::{BitDepth, ColorType, Error, InterlaceMethod};
+    use alloc::vec::Vec;
+    use core::convert::TryFrom;
+    use core::{ffi::CStr, slice};
     use spng_sys as sys;
-    use std::{ffi::CStr, slice};
 
     /// Safe wrapper for [`spng_sys::spng_splt`]
     #[repr(transparent)]
     pub struct Splt(pub(crate) sys::spng_splt);
 
     impl Splt {
-        pub fn name(&self) -> Result<&str, std::str::Utf8Error> {
+        pub fn name(&self) -> Result<&str, core::str::Utf8Error> {
             unsafe { CStr::from_ptr(self.0.name.as_ptr() as _).to_str() }
         }
 
@@ -584,9 +1296,29 @@ pub mod chunk {
 
     /// Safe wrapper for [`spng_sys::spng_plte`]
     #[repr(transparent)]
+    #[derive(Debug, Copy, Clone)]
     pub struct Plte(pub(crate) sys::spng_plte);
 
     impl Plte {
+        /// Creates a palette from up to `256` entries, returning `Error::ChunkSize` otherwise.
+        pub fn new(entries: &[PlteEntry]) -> Result<Plte, Error> {
+            let zero = PlteEntry {
+                red: 0,
+                green: 0,
+                blue: 0,
+                alpha: 0,
+            };
+            let mut plte = sys::spng_plte {
+                n_entries: entries.len() as u32,
+                entries: [zero; 256],
+            };
+            plte.entries
+                .get_mut(..entries.len())
+                .ok_or(Error::ChunkSize)?
+                .copy_from_slice(entries);
+            Ok(Plte(plte))
+        }
+
         pub fn entries(&self) -> &[PlteEntry] {
             unsafe { slice::from_raw_parts(self.0.entries.as_ptr(), self.0.n_entries as usize) }
         }
@@ -602,24 +1334,58 @@ pub mod chunk {
         }
     }
 
+    /// The chunk type of a [`Text`]
+    #[repr(u32)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    pub enum TextType {
+        /// Uncompressed Latin-1 text (`tEXt`)
+        Text = sys::spng_text_type_SPNG_TEXT,
+        /// Compressed Latin-1 text (`zTXt`)
+        Ztxt = sys::spng_text_type_SPNG_ZTXT,
+        /// International UTF-8 text (`iTXt`), compressed if `compression_flag` is set
+        Itxt = sys::spng_text_type_SPNG_ITXT,
+    }
+
+    impl TryFrom<i32> for TextType {
+        type Error = Error;
+        fn try_from(value: i32) -> Result<TextType, Error> {
+            match value as u32 {
+                sys::spng_text_type_SPNG_TEXT => Ok(TextType::Text),
+                sys::spng_text_type_SPNG_ZTXT => Ok(TextType::Ztxt),
+                sys::spng_text_type_SPNG_ITXT => Ok(TextType::Itxt),
+                _ => Err(Error::Text),
+            }
+        }
+    }
+
     /// Safe wrapper for [`spng_sys::spng_text`]
     #[repr(transparent)]
     pub struct Text(pub(crate) sys::spng_text);
 
     impl Text {
-        pub fn keyword(&self) -> Result<&str, std::str::Utf8Error> {
+        pub fn keyword(&self) -> Result<&str, core::str::Utf8Error> {
             unsafe { CStr::from_ptr(self.0.keyword.as_ptr() as _).to_str() }
         }
 
+        /// Returns the keyword, which is Latin-1 encoded, without the null terminator.
+        pub fn keyword_bytes(&self) -> &[u8] {
+            unsafe { CStr::from_ptr(self.0.keyword.as_ptr() as _).to_bytes() }
+        }
+
         pub fn type_(&self) -> i32 {
             self.0.type_
         }
 
+        /// Returns the chunk type, see also [`type_`](Text::type_)
+        pub fn text_type(&self) -> Result<TextType, Error> {
+            TextType::try_from(self.0.type_)
+        }
+
         pub fn length(&self) -> usize {
             self.0.length
         }
 
-        pub fn text(&self) -> Result<&str, std::str::Utf8Error> {
+        pub fn text(&self) -> Result<&str, core::str::Utf8Error> {
             unsafe { CStr::from_ptr(self.0.text).to_str() }
         }
 
@@ -635,11 +1401,11 @@ pub mod chunk {
             self.0.compression_method
         }
 
-        pub fn language_tag(&self) -> Result<&str, std::str::Utf8Error> {
+        pub fn language_tag(&self) -> Result<&str, core::str::Utf8Error> {
             unsafe { CStr::from_ptr(self.0.language_tag).to_str() }
         }
 
-        pub fn translated_keyword(&self) -> Result<&str, std::str::Utf8Error> {
+        pub fn translated_keyword(&self) -> Result<&str, core::str::Utf8Error> {
             unsafe { CStr::from_ptr(self.0.translated_keyword).to_str() }
         }
     }
@@ -649,7 +1415,7 @@ pub mod chunk {
     pub struct Iccp(pub(crate) sys::spng_iccp);
 
     impl Iccp {
-        pub fn profile_name(&self) -> Result<&str, std::str::Utf8Error> {
+        pub fn profile_name(&self) -> Result<&str, core::str::Utf8Error> {
             unsafe { CStr::from_ptr(self.0.profile_name.as_ptr()).to_str() }
         }
 
@@ -658,14 +1424,163 @@ pub mod chunk {
         }
     }
 
+    /// Safe wrapper for [`spng_sys::spng_bkgd`]
+    ///
+    /// Only the fields that apply to the image's color type are accessible.
+    #[derive(Debug, Copy, Clone)]
+    pub struct Bkgd {
+        pub(crate) bkgd: sys::spng_bkgd,
+        pub(crate) color_type: ColorType,
+    }
+
+    impl Bkgd {
+        /// Creates a background gray level for grayscale images.
+        pub fn from_gray(gray: u16) -> Bkgd {
+            Bkgd::new(ColorType::Grayscale, gray, (0, 0, 0), 0)
+        }
+
+        /// Creates a background color for truecolor images.
+        pub fn from_rgb(red: u16, green: u16, blue: u16) -> Bkgd {
+            Bkgd::new(ColorType::Truecolor, 0, (red, green, blue), 0)
+        }
+
+        /// Creates a background palette index for indexed images.
+        pub fn from_palette_index(index: u8) -> Bkgd {
+            Bkgd::new(ColorType::Indexed, 0, (0, 0, 0), index)
+        }
+
+        fn new(color_type: ColorType, gray: u16, rgb: (u16, u16, u16), index: u8) -> Bkgd {
+            let (red, green, blue) = rgb;
+            let bkgd = sys::spng_bkgd {
+                gray,
+                red,
+                green,
+                blue,
+                plte_index: index as u16,
+            };
+            Bkgd { bkgd, color_type }
+        }
+
+        /// Returns the gray level for grayscale images.
+        pub fn gray(&self) -> Option<u16> {
+            match self.color_type {
+                ColorType::Grayscale | ColorType::GrayscaleAlpha => Some(self.bkgd.gray),
+                _ => None,
+            }
+        }
+
+        /// Returns the `(red, green, blue)` color for truecolor images.
+        pub fn rgb(&self) -> Option<(u16, u16, u16)> {
+            match self.color_type {
+                ColorType::Truecolor | ColorType::TruecolorAlpha => {
+                    Some((self.bkgd.red, self.bkgd.green, self.bkgd.blue))
+                }
+                _ => None,
+            }
+        }
+
+        /// Returns the palette index for indexed images.
+        pub fn palette_index(&self) -> Option<u8> {
+            match self.color_type {
+                ColorType::Indexed => Some(self.bkgd.plte_index as u8),
+                _ => None,
+            }
+        }
+
+        /// Returns the color type of the image the chunk belongs to.
+        pub fn color_type(&self) -> ColorType {
+            self.color_type
+        }
+    }
+
+    /// Safe wrapper for [`spng_sys::spng_ihdr`], the image header
+    #[repr(transparent)]
+    #[derive(Debug, Copy, Clone)]
+    pub struct ImageHeader(pub(crate) sys::spng_ihdr);
+
+    impl ImageHeader {
+        pub fn width(&self) -> u32 {
+            self.0.width
+        }
+
+        pub fn height(&self) -> u32 {
+            self.0.height
+        }
+
+        pub fn bit_depth(&self) -> Result<BitDepth, Error> {
+            BitDepth::try_from(self.0.bit_depth)
+        }
+
+        pub fn color_type(&self) -> Result<ColorType, Error> {
+            ColorType::try_from(self.0.color_type)
+        }
+
+        /// Returns the interlace method. libspng rejects headers with an unknown method, so any
+        /// non-zero value is Adam7.
+        pub fn interlace_method(&self) -> InterlaceMethod {
+            match self.0.interlace_method {
+                0 => InterlaceMethod::None,
+                _ => InterlaceMethod::Adam7,
+            }
+        }
+
+        /// Returns the raw header.
+        pub fn ihdr(&self) -> &Ihdr {
+            &self.0
+        }
+    }
+
+    /// Safe wrapper for [`spng_sys::spng_phys`], the physical pixel dimensions
+    #[repr(transparent)]
+    #[derive(Debug, Copy, Clone)]
+    pub struct Phys(pub(crate) sys::spng_phys);
+
+    impl Phys {
+        /// The `unit_specifier` for pixels per meter. A value of `0` means the unit is unknown and
+        /// only the aspect ratio is defined.
+        pub const UNIT_METER: u8 = 1;
+
+        pub fn new(ppu_x: u32, ppu_y: u32, unit_specifier: u8) -> Phys {
+            Phys(sys::spng_phys {
+                ppu_x,
+                ppu_y,
+                unit_specifier,
+            })
+        }
+
+        /// Creates pixel dimensions in pixels per meter from dots per inch.
+        pub fn from_dpi(dpi_x: f64, dpi_y: f64) -> Phys {
+            let ppm = |dpi: f64| (dpi / 0.0254).round() as u32;
+            Phys::new(ppm(dpi_x), ppm(dpi_y), Phys::UNIT_METER)
+        }
+
+        /// Returns the pixels per unit as `(x, y)`.
+        pub fn pixels_per_unit(&self) -> (u32, u32) {
+            (self.0.ppu_x, self.0.ppu_y)
+        }
+
+        pub fn unit_specifier(&self) -> u8 {
+            self.0.unit_specifier
+        }
+
+        /// Returns the dots per inch as `(x, y)`, or `None` if the unit isn't meters.
+        pub fn dpi(&self) -> Option<(f64, f64)> {
+            if self.0.unit_specifier != Phys::UNIT_METER {
+                return None;
+            }
+            let dpi = |ppm: u32| ppm as f64 * 0.0254;
+            Some((dpi(self.0.ppu_x), dpi(self.0.ppu_y)))
+        }
+    }
+
     /// Safe wrapper for [`spng_sys::spng_unknown_chunk`]
     #[repr(transparent)]
     pub struct UnknownChunk(pub(crate) spng_sys::spng_unknown_chunk);
 
     impl UnknownChunk {
         /// Returns the chunk type.
-        pub fn type_(&self) -> Result<&str, std::str::Utf8Error> {
-            std::str::from_utf8(&self.0.type_)
+        pub fn type_(&self) -> Result<&str, core::str::Utf8Error> {
+            core::str::from_utf8(&self.0.type_)
         }
 
         /// Returns the chunk data.
@@ -674,6 +1589,31 @@ pub mod chunk {
         }
     }
 
+    /// Owned copy of an [`UnknownChunk`]
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct OwnedUnknownChunk {
+        /// The chunk type
+        pub type_: [u8; 4],
+        /// The chunk data
+        pub data: Vec<u8>,
+    }
+
+    impl OwnedUnknownChunk {
+        /// Returns the chunk type as a string.
+        pub fn type_str(&self) -> Result<&str, core::str::Utf8Error> {
+            core::str::from_utf8(&self.type_)
+        }
+    }
+
+    impl From<&UnknownChunk> for OwnedUnknownChunk {
+        fn from(chunk: &UnknownChunk) -> OwnedUnknownChunk {
+            OwnedUnknownChunk {
+                type_: chunk.0.type_,
+                data: chunk.data().to_vec(),
+            }
+        }
+    }
+
     /// Image header
     pub type Ihdr = sys::spng_ihdr;
     /// Transparency
@@ -684,12 +1624,8 @@ pub mod chunk {
     pub type ChrmInt = sys::spng_chrm_int;
     /// Significant bits
     pub type Sbit = sys::spng_sbit;
-    /// Background color
-    pub type Bkgd = sys::spng_bkgd;
     /// Histogram
     pub type Hist = sys::spng_hist;
-    /// Physical pixel dimensions
-    pub type Phys = sys::spng_phys;
     /// Modification time
     pub type Time = sys::spng_time;
     /// Offset
@@ -699,3 +1635,164 @@ pub mod chunk {
     /// Palette entry
     pub type PlteEntry = spng_sys::spng_plte_entry;
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    // libspng can't run under Miri, so this drives the trampoline directly with a pointer
+    // obtained the same way as `RawContext::set_stream`:
+    //
+    // MIRIFLAGS=-Zmiri-tree-borrows cargo +nightly miri test -p spng --lib
+    #[test]
+    fn read_fn_stream_pointer() {
+        let data = [1u8, 2, 3, 4];
+        let stream = NonNull::from(Box::leak(Box::new(Stream::new(&data[..]))));
+        let user = stream.as_ptr() as *mut libc::c_void;
+        let mut dest = [0u8; 2];
+        unsafe {
+            for chunk in data.chunks(2) {
+                let ret = read_fn::<&[u8]>(ptr::null_mut(), user, dest.as_mut_ptr() as _, 2);
+                assert_eq!(sys::spng_errno_SPNG_OK, ret);
+                assert_eq!(chunk, &dest[..]);
+                assert!((*stream.as_ptr()).error.take().is_none());
+            }
+            let ret = read_fn::<&[u8]>(ptr::null_mut(), user, dest.as_mut_ptr() as _, 2);
+            assert_eq!(sys::spng_errno_SPNG_IO_EOF, ret);
+            drop(Box::from_raw(stream.as_ptr()));
+        }
+    }
+
+    #[test]
+    fn write_fn_short_writes() {
+        /// Writes one byte per call, failing every other call with `Interrupted`
+        struct Trickle {
+            data: Vec<u8>,
+            interrupt: bool,
+        }
+
+        impl io::Write for Trickle {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.interrupt = !self.interrupt;
+                if self.interrupt {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                self.data.extend_from_slice(&buf[..1]);
+                Ok(1)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let trickle = Trickle {
+            data: Vec::new(),
+            interrupt: false,
+        };
+        let stream = NonNull::from(Box::leak(Box::new(Stream::new(trickle))));
+        let user = stream.as_ptr() as *mut libc::c_void;
+        let src = crate::PNG_SIGNATURE;
+        unsafe {
+            let ret = write_fn::<Trickle>(ptr::null_mut(), user, src.as_ptr() as _, src.len());
+            assert_eq!(sys::spng_errno_SPNG_OK, ret);
+            let stream = Box::from_raw(stream.as_ptr());
+            assert!(stream.error.is_none());
+            assert_eq!(&src[..], &stream.inner.data[..]);
+        }
+    }
+
+    #[test]
+    fn read_fn_input_limit() {
+        let data = [1u8, 2, 3, 4];
+        let mut stream = Stream::new(&data[..]);
+        stream.max_read = 3;
+        let stream = NonNull::from(Box::leak(Box::new(stream)));
+        let user = stream.as_ptr() as *mut libc::c_void;
+        let mut dest = [0u8; 2];
+        unsafe {
+            let ret = read_fn::<&[u8]>(ptr::null_mut(), user, dest.as_mut_ptr() as _, 2);
+            assert_eq!(sys::spng_errno_SPNG_OK, ret);
+            let ret = read_fn::<&[u8]>(ptr::null_mut(), user, dest.as_mut_ptr() as _, 2);
+            assert_eq!(sys::spng_errno_SPNG_IO_ERROR, ret);
+            let stream = Box::from_raw(stream.as_ptr());
+            let expected = Error::LimitsExceeded {
+                kind: LimitKind::InputBytes,
+                value: 4,
+                limit: 3,
+            };
+            assert_eq!(Some(expected), stream.error);
+        }
+    }
+
+    #[test]
+    fn read_fn_catches_panic() {
+        struct PanickingReader;
+        impl io::Read for PanickingReader {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                panic!("reader panicked")
+            }
+        }
+        let stream = NonNull::from(Box::leak(Box::new(Stream::new(PanickingReader))));
+        let user = stream.as_ptr() as *mut libc::c_void;
+        let mut dest = [0u8; 2];
+        unsafe {
+            let ret = read_fn::<PanickingReader>(ptr::null_mut(), user, dest.as_mut_ptr() as _, 2);
+            assert_eq!(sys::spng_errno_SPNG_IO_ERROR, ret);
+            let stream = Box::from_raw(stream.as_ptr());
+            let payload = stream.panic.expect("panic payload");
+            assert_eq!(Some(&"reader panicked"), payload.downcast_ref::<&str>());
+        }
+    }
+
+    // `get_text`, `get_splt` and `get_unknown_chunks` can't run under Miri either, so this drives
+    // `read_array` with getters that fill fewer entries than they announced or fail.
+    #[test]
+    fn read_array_partial() {
+        let mut calls = 0;
+        let partial = unsafe {
+            read_array(|array: *mut String, len| {
+                calls += 1;
+                if array.is_null() {
+                    *len = 3;
+                } else {
+                    array.write(String::from("text"));
+                    *len = 1;
+                }
+                Ok(())
+            })
+        };
+        assert_eq!(Ok(vec![String::from("text")]), partial);
+        assert_eq!(2, calls);
+
+        let failed = unsafe {
+            read_array(|array: *mut String, len| {
+                if array.is_null() {
+                    *len = 3;
+                    Ok(())
+                } else {
+                    Err(Error::Chunkavail)
+                }
+            })
+        };
+        assert_eq!(Err(Error::Chunkavail), failed);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn decoded_image_size_cached() -> Result<(), Error> {
+        let mut png = Vec::new();
+        let mut writer = crate::Encoder::new(&mut png, 2, 2).write_info()?;
+        writer.write_image_data(&[0; 16])?;
+        writer.finish()?;
+
+        let mut ctx = RawContext::new()?;
+        ctx.set_png_buffer(&png)?;
+        assert_eq!(16, ctx.decoded_image_size(Format::Rgba8)?);
+        assert_eq!(16, ctx.decoded_image_size(Format::Rgba8)?);
+        assert_eq!(1, ctx.image_size_queries.get());
+        assert_eq!(12, ctx.decoded_image_size(Format::Rgb8)?);
+        assert_eq!(2, ctx.image_size_queries.get());
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{is_png, Error, PNG_SIGNATURE};
+
+/// The CRC-32 lookup table of the `png` specification, built at compile time
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// Computes the CRC of a chunk's type and data
+fn crc(bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Checks the structure of the `png` in `bytes` without decoding it: the signature, the length,
+/// type and CRC of every chunk up to `IEND`, and the position of the critical chunks.
+///
+/// `IDAT` is not inflated, so corrupt image data is only detected when decoding, and the contents
+/// of other chunks aren't checked either. This is meant for rejecting malformed files cheaply
+/// before a full decode. Data after `IEND` is ignored, as libspng does.
+///
+/// Returns the same errors as decoding would, e.g. `Error::Signature`, `Error::ChunkCrc`,
+/// `Error::ChunkPos` for misplaced chunks and `Error::IoEof` if the data ends before `IEND`.
+pub fn validate_structure(bytes: &[u8]) -> Result<(), Error> {
+    if !is_png(bytes) {
+        return Err(Error::Signature);
+    }
+    let mut rest = &bytes[PNG_SIGNATURE.len()..];
+    let mut first = true;
+    let mut seen_plte = false;
+    let mut seen_idat = false;
+    let mut prev_idat = false;
+    loop {
+        if rest.len() < 8 {
+            return Err(Error::IoEof);
+        }
+        let length = read_u32(rest);
+        if length > i32::MAX as u32 {
+            return Err(Error::ChunkSize);
+        }
+        let end = 8 + length as usize;
+        if rest.len() < end + 4 {
+            return Err(Error::IoEof);
+        }
+        let chunk_type = [rest[4], rest[5], rest[6], rest[7]];
+        if !chunk_type.iter().all(u8::is_ascii_alphabetic) {
+            return Err(Error::ChunkType);
+        }
+        if crc(&rest[4..end]) != read_u32(&rest[end..]) {
+            return Err(Error::ChunkCrc);
+        }
+        let is_idat = &chunk_type == b"IDAT";
+        if first {
+            if &chunk_type != b"IHDR" {
+                return Err(Error::Noihdr);
+            }
+            if length != 13 {
+                return Err(Error::IhdrSize);
+            }
+        } else {
+            match &chunk_type {
+                b"IHDR" => return Err(Error::ChunkPos),
+                b"PLTE" if seen_plte => return Err(Error::DupPlte),
+                b"PLTE" if seen_idat => return Err(Error::ChunkPos),
+                b"PLTE" => seen_plte = true,
+                b"IDAT" if seen_idat && !prev_idat => return Err(Error::ChunkPos),
+                b"IDAT" => seen_idat = true,
+                b"IEND" if !seen_idat => return Err(Error::ChunkPos),
+                b"IEND" if length != 0 => return Err(Error::ChunkSize),
+                b"IEND" => return Ok(()),
+                // The case of the first letter marks a chunk as critical
+                _ if chunk_type[0].is_ascii_uppercase() => return Err(Error::ChunkUnknownCritical),
+                _ => {}
+            }
+        }
+        first = false;
+        prev_idat = is_idat;
+        rest = &rest[end + 4..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_crc() {
+        // The CRC of an empty `IEND` chunk
+        assert_eq!(0xae42_6082, crc(b"IEND"));
+        assert_eq!(Err(Error::Signature), validate_structure(b"GIF89a"));
+        assert_eq!(Err(Error::IoEof), validate_structure(&PNG_SIGNATURE));
+    }
+}
@@ -0,0 +1,132 @@
+//! Synthesized test images covering every color type, bit depth, and interlace method.
+//!
+//! The images are encoded at runtime so the test suite doesn't need a checked-in PNG for every
+//! combination.
+
+use spng::{
+    raw::{
+        chunk::{Ihdr, Plte, PlteEntry},
+        RawContext,
+    },
+    BitDepth, ColorType, ContextFlags, EncodeFlags, Error, Format,
+};
+
+/// Every valid color type and bit depth combination
+pub const COMBINATIONS: &[(ColorType, &[BitDepth])] = &[
+    (
+        ColorType::Grayscale,
+        &[
+            BitDepth::One,
+            BitDepth::Two,
+            BitDepth::Four,
+            BitDepth::Eight,
+            BitDepth::Sixteen,
+        ],
+    ),
+    (ColorType::Truecolor, &[BitDepth::Eight, BitDepth::Sixteen]),
+    (
+        ColorType::Indexed,
+        &[
+            BitDepth::One,
+            BitDepth::Two,
+            BitDepth::Four,
+            BitDepth::Eight,
+        ],
+    ),
+    (
+        ColorType::GrayscaleAlpha,
+        &[BitDepth::Eight, BitDepth::Sixteen],
+    ),
+    (
+        ColorType::TruecolorAlpha,
+        &[BitDepth::Eight, BitDepth::Sixteen],
+    ),
+];
+
+/// A synthesized PNG and the image data it was encoded from
+#[derive(Debug)]
+pub struct Fixture {
+    pub color_type: ColorType,
+    pub bit_depth: BitDepth,
+    pub interlaced: bool,
+    pub width: u32,
+    pub height: u32,
+    /// The image data in `Format::Png`
+    pub data: Vec<u8>,
+    /// The encoded PNG
+    pub png: Vec<u8>,
+}
+
+/// Generates a `7x5` fixture for every combination in [`COMBINATIONS`], with and without
+/// interlacing. The odd dimensions leave some of the Adam7 passes partially filled.
+pub fn all() -> Result<Vec<Fixture>, Error> {
+    let mut fixtures = Vec::new();
+    for &(color_type, bit_depths) in COMBINATIONS {
+        for &bit_depth in bit_depths {
+            for &interlaced in &[false, true] {
+                fixtures.push(generate(color_type, bit_depth, 7, 5, interlaced)?);
+            }
+        }
+    }
+    Ok(fixtures)
+}
+
+/// Generates a `width` by `height` image with a deterministic pattern. Indexed images get a
+/// palette with an entry for every possible index.
+pub fn generate(
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    width: u32,
+    height: u32,
+    interlaced: bool,
+) -> Result<Fixture, Error> {
+    let bits = bit_depth as usize;
+    let line_bits = width as usize * color_type.samples() * bits;
+    let line_size = line_bits.div_ceil(8);
+    let mut data = Vec::with_capacity(line_size * height as usize);
+    for y in 0..height as usize {
+        let start = data.len();
+        data.extend((0..line_size).map(|x| (x * 31 + y * 17 + 5) as u8));
+        // zero the padding bits at the end of the row
+        let padding = line_size * 8 - line_bits;
+        data[start + line_size - 1] &= 0xff << padding;
+    }
+
+    let ihdr = Ihdr {
+        width,
+        height,
+        bit_depth: bit_depth as u8,
+        color_type: color_type as u8,
+        compression_method: 0,
+        filter_method: 0,
+        interlace_method: interlaced as u8,
+    };
+    let mut png = Vec::new();
+    {
+        let mut ctx = RawContext::with_flags(ContextFlags::ENCODER)?;
+        ctx.set_png_stream_writer(&mut png)?;
+        ctx.set_ihdr(&ihdr)?;
+        if color_type == ColorType::Indexed {
+            let entries = (0..1usize << bits)
+                .map(|i| PlteEntry {
+                    red: i as u8,
+                    green: (i * 3) as u8,
+                    blue: (i * 7) as u8,
+                    alpha: 255,
+                })
+                .collect::<Vec<_>>();
+            ctx.set_plte(&Plte::new(&entries)?)?;
+        }
+        ctx.encode_image(&data, Format::Png, EncodeFlags::FINALIZE)?;
+    }
+
+    Ok(Fixture {
+        color_type,
+        bit_depth,
+        interlaced,
+        width,
+        height,
+        data,
+        png,
+    })
+}
@@ -1,8 +1,27 @@
-use spng::{raw::ChunkAvail, BitDepth, ColorType, Decoder};
-use std::io::{BufReader, Cursor, Read};
+mod common;
+
+use spng::{raw::ChunkAvail, BitDepth, ColorType, Decoder, InterlaceMethod};
+use std::io::{self, BufReader, Cursor, Read};
 
 static TEST_PNG_001: &[u8] = include_bytes!("test-001.png");
 static TEST_PNG_002: &[u8] = include_bytes!("test-002.png");
+static TEST_PNG_004: &[u8] = include_bytes!("test-004.png");
+static TEST_PNG_005: &[u8] = include_bytes!("test-005.png");
+static TEST_PNG_006: &[u8] = include_bytes!("test-006.png");
+static TEST_PNG_007: &[u8] = include_bytes!("test-007.png");
+#[cfg(feature = "chrono")]
+static TEST_PNG_009: &[u8] = include_bytes!("test-009.png");
+static TEST_PNG_011: &[u8] = include_bytes!("test-011.png");
+static TEST_PNG_012: &[u8] = include_bytes!("test-012.png");
+static TEST_PNG_013: &[u8] = include_bytes!("test-013.png");
+static TEST_PNG_014: &[u8] = include_bytes!("test-014.png");
+static TEST_PNG_015: &[u8] = include_bytes!("test-015.png");
+static TEST_PNG_016: &[u8] = include_bytes!("test-016.png");
+static TEST_PNG_018: &[u8] = include_bytes!("test-018.png");
+static TEST_PNG_019: &[u8] = include_bytes!("test-019.png");
+static TEST_PNG_020: &[u8] = include_bytes!("test-020.png");
+static TEST_PNG_021: &[u8] = include_bytes!("test-021.png");
+static TEST_PNG_022: &[u8] = include_bytes!("test-022.png");
 
 fn check_decoder<R: Read>(
     decoder: Decoder<R>,
@@ -21,10 +40,7 @@ fn check_decoder<R: Read>(
     let mut out = vec![0; output_buffer_size];
     let out_info = reader.next_frame(&mut out).expect("next_frame failed");
     assert_eq!(output_buffer_size, out_info.buffer_size());
-    assert_eq!(info.width, out_info.width);
-    assert_eq!(info.height, out_info.height);
-    assert_eq!(info.bit_depth, out_info.bit_depth);
-    assert_eq!(info.color_type, out_info.color_type);
+    assert!(out_info.matches_info(&info));
 }
 
 #[test]
@@ -68,6 +84,37 @@ fn decode_002_cursor_buffered() {
     check_decoder(decoder, 380, 287, BitDepth::Eight, ColorType::RGBA);
 }
 
+#[test]
+fn decode_bytes() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_002, spng::Format::Rgba8)?;
+    let image = spng::decode_bytes(TEST_PNG_002, spng::Format::Rgba8)?;
+    assert_eq!(out_info, image.info);
+    assert_eq!(data, image.data);
+    assert_eq!(
+        Err(spng::Error::Fmt),
+        spng::decode_bytes(TEST_PNG_002, spng::Format::G8).map(|_| ())
+    );
+    Ok(())
+}
+
+#[test]
+fn decode_g16() -> Result<(), Box<dyn std::error::Error>> {
+    let fixture = common::generate(ColorType::Grayscale, BitDepth::Sixteen, 7, 5, false)?;
+    let (out_info, gray) = spng::decode(fixture.png.as_slice(), spng::Format::Png)?;
+    assert_eq!(ColorType::Grayscale, out_info.color_type);
+    assert_eq!(BitDepth::Sixteen, out_info.bit_depth);
+    assert_eq!(fixture.data, gray);
+
+    let (out_info, gray_alpha) = spng::decode(fixture.png.as_slice(), spng::Format::Ga16)?;
+    assert_eq!(ColorType::GrayscaleAlpha, out_info.color_type);
+    assert_eq!(BitDepth::Sixteen, out_info.bit_depth);
+    for (g, ga) in gray.chunks_exact(2).zip(gray_alpha.chunks_exact(4)) {
+        assert_eq!(g, &ga[..2]);
+        assert_eq!(&[0xff, 0xff], &ga[2..]);
+    }
+    Ok(())
+}
+
 #[test]
 fn decode_002_slice() {
     let decoder = Decoder::new(TEST_PNG_002);
@@ -75,6 +122,222 @@ fn decode_002_slice() {
     check_decoder(decoder, 380, 287, BitDepth::Eight, ColorType::RGBA);
 }
 
+#[test]
+fn decode_generated() -> Result<(), Box<dyn std::error::Error>> {
+    for fixture in common::all()? {
+        let (out_info, data) = spng::decode(fixture.png.as_slice(), spng::Format::Png)?;
+        let description = format!(
+            "{:?}",
+            (fixture.color_type, fixture.bit_depth, fixture.interlaced)
+        );
+        assert_eq!(fixture.color_type, out_info.color_type, "{}", description);
+        assert_eq!(fixture.bit_depth, out_info.bit_depth, "{}", description);
+        assert_eq!(
+            (fixture.width, fixture.height),
+            (out_info.width, out_info.height)
+        );
+        assert_eq!(fixture.data, data, "{}", description);
+
+        let (_, rgba) = spng::decode(fixture.png.as_slice(), spng::Format::Rgba8)?;
+        assert_eq!(
+            (fixture.width * fixture.height * 4) as usize,
+            rgba.len(),
+            "{}",
+            description
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_001_rows() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, expected) = spng::decode(TEST_PNG_001, spng::Format::Png)?;
+    let line_size = out_info.line_size();
+
+    let mut reader = Decoder::new(TEST_PNG_001).read_info()?;
+    let mut rows = reader.rows()?;
+    let mut count = 0;
+    while let Some(row) = rows.next_row() {
+        let start = count * line_size;
+        assert_eq!(&expected[start..start + line_size], row?);
+        count += 1;
+    }
+    assert_eq!(out_info.height as usize, count);
+
+    let mut reader = Decoder::new(TEST_PNG_001).read_info()?;
+    let rows = reader.rows_buffered()?.collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(expected, rows.concat());
+    Ok(())
+}
+
+#[test]
+fn decode_as() -> Result<(), Box<dyn std::error::Error>> {
+    let (expected_info, expected) = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    let mut reader = Decoder::new(TEST_PNG_001)
+        .with_output_format(spng::Format::Png)
+        .read_info()?;
+    let mut out = vec![0; expected_info.buffer_size - 1];
+    assert_eq!(
+        Err(spng::Error::Bufsiz),
+        reader.decode_as(&mut out, spng::Format::Rgba8, spng::DecodeFlags::empty())
+    );
+    assert_eq!(
+        Err(spng::Error::OpState),
+        reader.decode_as(
+            &mut out,
+            spng::Format::Rgba8,
+            spng::DecodeFlags::PROGRESSIVE
+        )
+    );
+    // 001 is RGBA, which libspng can't decode to grayscale
+    assert_eq!(
+        Err(spng::Error::Fmt),
+        reader.decode_as(&mut out, spng::Format::G8, spng::DecodeFlags::empty())
+    );
+    out.push(0);
+    let out_info = reader.decode_as(&mut out, spng::Format::Rgba8, spng::DecodeFlags::empty())?;
+    assert_eq!(expected_info, out_info);
+    assert_eq!(expected, out);
+    Ok(())
+}
+
+#[test]
+fn next_image() -> Result<(), Box<dyn std::error::Error>> {
+    let first = common::generate(ColorType::Truecolor, BitDepth::Eight, 16, 16, false)?;
+    let mut stream = first.png.clone();
+    stream.extend_from_slice(TEST_PNG_004);
+    let mut reader = Decoder::new(stream.as_slice()).read_info()?;
+    let mut sizes = Vec::new();
+    loop {
+        let mut out = vec![0; reader.output_buffer_size()];
+        let out_info = reader.next_frame(&mut out)?;
+        sizes.push((out_info.width, out_info.height));
+        match reader.next_image()? {
+            Some(next) => reader = next,
+            None => break,
+        }
+    }
+    assert_eq!(vec![(16, 16), (4, 4)], sizes);
+
+    let truncated = &stream[..first.png.len() + 16];
+    let mut reader = Decoder::new(truncated).read_info()?;
+    let mut out = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut out)?;
+    assert!(reader.next_image().is_err());
+    Ok(())
+}
+
+#[test]
+fn decode_001_scanlines() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, expected) = spng::decode(TEST_PNG_001, spng::Format::Png)?;
+    let mut reader = Decoder::new(TEST_PNG_001).read_info()?;
+    let mut scanlines = reader.scanlines()?;
+    let mut decoded = Vec::new();
+    while let Some(scanline) = scanlines.next_scanline() {
+        decoded.extend_from_slice(scanline?);
+    }
+    assert_eq!(out_info.buffer_size, decoded.len());
+    assert_eq!(expected, decoded);
+
+    let interlaced = common::generate(ColorType::Grayscale, BitDepth::Eight, 1, 1, true)?;
+    let mut reader = Decoder::new(interlaced.png.as_slice()).read_info()?;
+    assert_eq!(InterlaceMethod::Adam7, reader.info().interlace_method);
+    assert_eq!(
+        Err(spng::Error::InterlaceMethod),
+        reader.scanlines().map(|_| ())
+    );
+    Ok(())
+}
+
+#[test]
+fn output_info_matches_info() -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = Decoder::new(TEST_PNG_001).read_info()?;
+    let info = reader.info();
+    let mut out = vec![0; reader.output_buffer_size()];
+    let out_info = reader.next_frame(&mut out)?;
+    assert!(out_info.matches_info(&info));
+    assert_eq!(info, spng::Info::from(out_info));
+
+    let truecolor = common::generate(ColorType::Truecolor, BitDepth::Eight, 16, 16, false)?;
+    let mut reader = Decoder::new(truecolor.png.as_slice())
+        .with_output_format(spng::Format::Rgba8)
+        .read_info()?;
+    let info = reader.info();
+    let mut out = vec![0; reader.output_buffer_size()];
+    let out_info = reader.next_frame(&mut out)?;
+    assert!(!out_info.matches_info(&info));
+    Ok(())
+}
+
+#[test]
+fn output_format_compatibility() -> Result<(), Box<dyn std::error::Error>> {
+    use spng::Format;
+    let read_info = |data, format| {
+        Decoder::new(data)
+            .with_output_format(format)
+            .read_info()
+            .map(|_| ())
+    };
+    // truecolor alpha 8-bit
+    for &format in &[Format::Rgba8, Format::Rgba16, Format::Rgb8, Format::Png] {
+        assert_eq!(Ok(()), read_info(TEST_PNG_001, format));
+    }
+    for &format in &[Format::G8, Format::Ga8, Format::Ga16] {
+        assert_eq!(Err(spng::Error::Fmt), read_info(TEST_PNG_001, format));
+    }
+    // grayscale 8-bit and 1-bit
+    let g1 = common::generate(ColorType::Grayscale, BitDepth::One, 10, 2, false)?;
+    for &data in &[TEST_PNG_004, g1.png.as_slice()] {
+        assert_eq!(Ok(()), read_info(data, Format::G8));
+        assert_eq!(Ok(()), read_info(data, Format::Ga8));
+        assert_eq!(Ok(()), read_info(data, Format::Rgba8));
+        assert_eq!(Err(spng::Error::Fmt), read_info(data, Format::Ga16));
+    }
+    // indexed
+    assert_eq!(Ok(()), read_info(TEST_PNG_006, Format::Rgba8));
+    assert_eq!(Err(spng::Error::Fmt), read_info(TEST_PNG_006, Format::G8));
+    assert!(!Format::G8.supports_decode_from(ColorType::Truecolor, BitDepth::Eight));
+    assert!(Format::Ga16.supports_decode_from(ColorType::Grayscale, BitDepth::Sixteen));
+    Ok(())
+}
+
+#[test]
+fn reader_accessors() -> Result<(), Box<dyn std::error::Error>> {
+    let reader = Decoder::new(TEST_PNG_001)
+        .with_output_format(spng::Format::Rgb8)
+        .with_decode_flags(spng::DecodeFlags::GAMMA)
+        .read_info()?;
+    assert_eq!(spng::Format::Rgb8, reader.output_format());
+    assert_eq!(spng::DecodeFlags::GAMMA, reader.decode_flags());
+    Ok(())
+}
+
+#[test]
+fn context_type() -> Result<(), Box<dyn std::error::Error>> {
+    let decoder = Decoder::new(TEST_PNG_001).with_context_flags(spng::ContextFlags::ENCODER);
+    assert_eq!(Err(spng::Error::CtxType), decoder.read_info().map(|_| ()));
+
+    let mut writer = spng::Encoder::new(Vec::new(), 1, 1)
+        .with_context_flags(spng::ContextFlags::empty())
+        .write_info()?;
+    writer.write_image_data(&[0, 0, 0, 255])?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[test]
+fn is_native_format() -> Result<(), Box<dyn std::error::Error>> {
+    let native = |format| -> Result<bool, spng::Error> {
+        let decoder = Decoder::new(TEST_PNG_001).with_output_format(format);
+        Ok(decoder.read_info()?.is_native_format())
+    };
+    assert!(native(spng::Format::Png)?);
+    assert!(native(spng::Format::Rgba8)?);
+    assert!(!native(spng::Format::Rgb8)?);
+    assert!(!native(spng::Format::Rgba16)?);
+    Ok(())
+}
+
 #[test]
 fn decode() -> Result<(), Box<dyn std::error::Error>> {
     let (out_info, out) = spng::decode(TEST_PNG_001, spng::Format::Png)?;
@@ -86,12 +349,266 @@ fn decode() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn unpack_to_g8() -> Result<(), Box<dyn std::error::Error>> {
+    let fixture = common::generate(ColorType::Grayscale, BitDepth::One, 10, 2, false)?;
+    let (out_info, data) = spng::decode(fixture.png.as_slice(), spng::Format::Png)?;
+    assert_eq!(BitDepth::One, out_info.bit_depth);
+    let packed = spng::DecodedImage::new(out_info, data);
+    let unpacked = packed.unpack_to_g8()?;
+    assert_eq!(BitDepth::Eight, unpacked.info.bit_depth);
+    assert_eq!(10, unpacked.info.line_size());
+    // Each row is two bytes, the most significant bit is the first pixel
+    let expected = (0..2)
+        .flat_map(|y| (0..10).map(move |x| (y, x)))
+        .map(|(y, x)| (fixture.data[y * 2 + x / 8] >> (7 - x % 8)) & 1)
+        .collect::<Vec<u8>>();
+    assert_eq!(expected, unpacked.data);
+    assert_eq!(
+        packed.data,
+        spng::pack_g1(&unpacked.data, unpacked.info.width)
+    );
+    Ok(())
+}
+
+#[test]
+fn flip_y() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    let line_size = out_info.line_size();
+    let original = spng::DecodedImage::new(out_info, data);
+    let mut image = original.clone();
+    image.flip_y();
+    assert_eq!(
+        &original.data[..line_size],
+        &image.data[image.data.len() - line_size..]
+    );
+    image.flip_y();
+    assert_eq!(original, image);
+
+    let fixture = common::generate(ColorType::Grayscale, BitDepth::One, 10, 2, false)?;
+    let (out_info, data) = spng::decode(fixture.png.as_slice(), spng::Format::Png)?;
+    let mut image = spng::DecodedImage::new(out_info, data);
+    image.flip_y();
+    assert_eq!(
+        [&fixture.data[2..], &fixture.data[..2]].concat(),
+        image.data
+    );
+
+    let odd = spng::OutputInfo {
+        height: 3,
+        width: 1,
+        color_type: ColorType::Grayscale,
+        bit_depth: BitDepth::Eight,
+        buffer_size: 3,
+    };
+    let mut image = spng::DecodedImage::new(odd, vec![1, 2, 3]);
+    image.flip_y();
+    assert_eq!(vec![3, 2, 1], image.data);
+    Ok(())
+}
+
+#[test]
+fn line_size_zero_height() {
+    let info = spng::OutputInfo {
+        width: 4,
+        height: 0,
+        color_type: ColorType::TruecolorAlpha,
+        bit_depth: BitDepth::Eight,
+        buffer_size: 16,
+    };
+    assert_eq!(0, info.line_size());
+    let mut image = spng::DecodedImage::new(info, vec![0; 16]);
+    image.flip_y();
+    assert_eq!(None, image.crop(0, 0, 1, 1));
+    assert_eq!(None, image.resize_nearest(1, 1));
+}
+
+#[test]
+fn is_opaque() {
+    let info = spng::OutputInfo {
+        width: 2,
+        height: 1,
+        color_type: ColorType::TruecolorAlpha,
+        bit_depth: BitDepth::Eight,
+        buffer_size: 8,
+    };
+    let opaque = spng::DecodedImage::new(info, vec![1, 2, 3, 255, 4, 5, 6, 255]);
+    assert!(opaque.is_opaque());
+    let transparent = spng::DecodedImage::new(info, vec![1, 2, 3, 255, 4, 5, 6, 254]);
+    assert!(!transparent.is_opaque());
+
+    let info = spng::OutputInfo {
+        color_type: ColorType::GrayscaleAlpha,
+        bit_depth: BitDepth::Sixteen,
+        ..info
+    };
+    let opaque = spng::DecodedImage::new(info, vec![0, 0, 255, 255, 1, 1, 255, 255]);
+    assert!(opaque.is_opaque());
+    let transparent = spng::DecodedImage::new(info, vec![0, 0, 255, 255, 1, 1, 255, 0]);
+    assert!(!transparent.is_opaque());
+
+    let info = spng::OutputInfo {
+        color_type: ColorType::Truecolor,
+        bit_depth: BitDepth::Eight,
+        buffer_size: 6,
+        ..info
+    };
+    assert!(spng::DecodedImage::new(info, vec![0; 6]).is_opaque());
+}
+
+#[cfg(feature = "bytemuck")]
+#[test]
+fn as_rgba8() -> Result<(), Box<dyn std::error::Error>> {
+    let fixture = common::generate(ColorType::Truecolor, BitDepth::Eight, 16, 16, false)?;
+    let (out_info, data) = spng::decode(fixture.png.as_slice(), spng::Format::Rgba8)?;
+    let image = spng::DecodedImage::new(out_info, data);
+    let pixels = image.as_rgba8().expect("rgba8 pixels");
+    assert_eq!(16 * 16, pixels.len());
+    assert_eq!(fixture.data[..3], pixels[0][..3]);
+    assert_eq!(255, pixels[0][3]);
+    assert_eq!(None, image.as_rgb8());
+    assert_eq!(None, image.as_rgba16());
+    Ok(())
+}
+
+#[cfg(feature = "ndarray")]
+#[test]
+fn to_ndarray() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    let image = spng::DecodedImage::new(out_info, data);
+    let array = image.to_ndarray().expect("8-bit image");
+    assert_eq!(&[300, 300, 4], array.shape());
+    let line_size = image.info.line_size();
+    let (y, x) = (150, 120);
+    for c in 0..4 {
+        assert_eq!(image.data[y * line_size + x * 4 + c], array[[y, x, c]]);
+    }
+    assert_eq!(None, image.to_ndarray_u16());
+
+    let fixture = common::generate(ColorType::TruecolorAlpha, BitDepth::Sixteen, 3, 2, false)?;
+    let (out_info, data) = spng::decode(fixture.png.as_slice(), spng::Format::Rgba16)?;
+    let image = spng::DecodedImage::new(out_info, data);
+    let array = image.to_ndarray_u16().expect("16-bit image");
+    assert_eq!(&[2, 3, 4], array.shape());
+    let sample = u16::from_ne_bytes([image.data[24 + 8], image.data[24 + 9]]);
+    assert_eq!(sample, array[[1, 1, 0]]);
+    assert_eq!(None, image.to_ndarray());
+    Ok(())
+}
+
+#[test]
+fn sample_average_color() -> Result<(), Box<dyn std::error::Error>> {
+    let fixture = common::generate(ColorType::Truecolor, BitDepth::Eight, 16, 16, false)?;
+    let color = spng::sample_average_color(fixture.png.as_slice())?;
+    let pixels = fixture.data.len() / 3;
+    for (channel, &average) in color[..3].iter().enumerate() {
+        let sum: usize = fixture.data[channel..]
+            .iter()
+            .step_by(3)
+            .map(|&sample| sample as usize)
+            .sum();
+        assert_eq!((sum + pixels / 2) / pixels, average as usize);
+    }
+    assert_eq!(255, color[3]);
+    Ok(())
+}
+
+// The WASI test runner doesn't support spawning threads
+#[cfg(not(target_os = "wasi"))]
+#[cfg(not(miri))]
+#[test]
+fn decode_002_concurrent() -> Result<(), Box<dyn std::error::Error>> {
+    fn decode() -> Result<Vec<u8>, spng::Error> {
+        let mut ctx = spng::raw::RawContext::new()?;
+        ctx.set_png_buffer(TEST_PNG_002)?;
+        let mut data = vec![0; ctx.decoded_image_size(spng::Format::Rgba8)?];
+        ctx.decode_image(&mut data, spng::Format::Rgba8, spng::DecodeFlags::empty())?;
+        Ok(data)
+    }
+
+    let expected = decode()?;
+    let threads: Vec<_> = (0..8)
+        .map(|_| std::thread::spawn(|| (0..16).map(|_| decode()).collect::<Vec<_>>()))
+        .collect();
+    for thread in threads {
+        for data in thread.join().expect("decode thread panicked") {
+            assert_eq!(expected, data?);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_owned_buffer() -> Result<(), Box<dyn std::error::Error>> {
+    fn decode<B: std::ops::Deref<Target = [u8]>>(buf: B) -> Result<Vec<u8>, spng::Error> {
+        let mut ctx = spng::raw::RawContext::new()?;
+        ctx.set_png_owned_buffer(buf)?;
+        let mut data = vec![0; ctx.decoded_image_size(spng::Format::Rgba8)?];
+        ctx.decode_image(&mut data, spng::Format::Rgba8, spng::DecodeFlags::empty())?;
+        Ok(data)
+    }
+
+    let (_, expected) = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    let owned: Vec<u8> = TEST_PNG_001.into();
+    assert_eq!(expected, decode(owned)?);
+
+    // memmap2 only supports unix and windows, it fails at runtime on wasi
+    #[cfg(any(unix, windows))]
+    {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test-001.png");
+        let file = std::fs::File::open(path)?;
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        assert_eq!(expected, decode(mmap)?);
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_redecode() -> Result<(), Box<dyn std::error::Error>> {
+    let decoder = Decoder::new(TEST_PNG_001)
+        .with_output_format(spng::Format::Rgba8)
+        .buffered();
+    let mut reader = decoder.read_info()?;
+    let mut rgba = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut rgba)?;
+    let (rgb_info, rgb) = reader.redecode(spng::Format::Rgb8)?;
+
+    let (_, expected_rgba) = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    let (expected_info, expected_rgb) = spng::decode(TEST_PNG_001, spng::Format::Rgb8)?;
+    assert_eq!(expected_rgba, rgba);
+    assert_eq!(expected_info, rgb_info);
+    assert_eq!(expected_rgb, rgb);
+    Ok(())
+}
+
+#[test]
+fn get_image_header() -> Result<(), Box<dyn std::error::Error>> {
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_001)?;
+    let header = ctx.get_image_header()?;
+    assert_eq!(300, header.width());
+    assert_eq!(300, header.height());
+    assert_eq!(BitDepth::Eight, header.bit_depth()?);
+    assert_eq!(ColorType::TruecolorAlpha, header.color_type()?);
+    assert_eq!(InterlaceMethod::None, header.interlace_method());
+    assert_eq!(ctx.get_ihdr()?.width, header.ihdr().width);
+
+    let interlaced = common::generate(ColorType::Grayscale, BitDepth::Eight, 1, 1, true)?;
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(&interlaced.png)?;
+    assert_eq!(
+        InterlaceMethod::Adam7,
+        ctx.get_image_header()?.interlace_method()
+    );
+    Ok(())
+}
+
 #[test]
 fn decode_001_raw_context() -> Result<(), Box<dyn std::error::Error>> {
     use std::convert::TryFrom;
     let out_format = spng::Format::Rgba8;
     let mut ctx = spng::raw::RawContext::new()?;
-    ctx.set_png_stream(TEST_PNG_001)?;
+    ctx.set_png_stream_reader(TEST_PNG_001)?;
     let ihdr = ctx.get_ihdr()?;
     assert_eq!(300, ihdr.width);
     assert_eq!(300, ihdr.height);
@@ -106,10 +623,1459 @@ fn decode_001_raw_context() -> Result<(), Box<dyn std::error::Error>> {
         .expect("text chunk in test image");
     let text_str = text[0].text()?;
     assert_eq!("Created with GIMP", text_str);
+    assert_eq!(spng::raw::chunk::TextType::Text, text[0].text_type()?);
+    Ok(())
+}
+
+#[test]
+fn get_text_skips_corrupt_chunk() -> Result<(), Box<dyn std::error::Error>> {
+    // test-016.png has a zTXt chunk with a bad CRC between two valid tEXt chunks
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_016)?;
+    ctx.decode_chunks()?;
+    let text = ctx.get_text()?;
+    assert_eq!(2, text.len());
+    assert_eq!("Title", text[0].keyword()?);
+    assert_eq!("good", text[0].text()?);
+    assert_eq!("Author", text[1].keyword()?);
+    assert_eq!("spng", text[1].text()?);
+    Ok(())
+}
+
+#[test]
+fn get_text_into() -> Result<(), Box<dyn std::error::Error>> {
+    let mut buffer = spng::raw::TextBuffer::new();
+    {
+        let mut ctx = spng::raw::RawContext::new()?;
+        ctx.set_png_buffer(TEST_PNG_014)?;
+        ctx.decode_chunks()?;
+        let text = ctx.get_text_into(&mut buffer)?;
+        assert_eq!(2, text.len());
+        assert_eq!("Title", text[0].keyword()?);
+        assert_eq!("First", text[0].text()?);
+        assert_eq!("Author", text[1].keyword()?);
+        assert_eq!("Second", text[1].text()?);
+    }
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_001)?;
+    ctx.decode_chunks()?;
+    let text = ctx.get_text_into(&mut buffer)?;
+    assert_eq!(1, text.len());
+    assert_eq!("Created with GIMP", text[0].text()?);
+    Ok(())
+}
+
+#[test]
+fn read_trailing_chunks() -> Result<(), Box<dyn std::error::Error>> {
+    // test-015 has a tEXt chunk after the image data
+    let mut reader = Decoder::new(TEST_PNG_015).read_info()?;
+    assert_eq!(
+        None,
+        reader.raw_context().get_text().chunk_avail()?.map(|_| ())
+    );
+    reader.read_trailing_chunks()?;
+    let text = reader.raw_context().get_text()?;
+    assert_eq!(1, text.len());
+    assert_eq!("Comment", text[0].keyword()?);
+    assert_eq!("After IDAT", text[0].text()?);
+    Ok(())
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn get_time_chrono() -> Result<(), Box<dyn std::error::Error>> {
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_009)?;
+    ctx.decode_chunks()?;
+    let expected = chrono::NaiveDate::from_ymd_opt(2020, 2, 29)
+        .and_then(|date| date.and_hms_opt(12, 34, 56))
+        .unwrap();
+    assert_eq!(expected, ctx.get_time_chrono()?);
+    Ok(())
+}
+
+#[test]
+fn validate() -> Result<(), Box<dyn std::error::Error>> {
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_001)?;
+    ctx.validate()?;
+
+    let truncated = &TEST_PNG_001[..TEST_PNG_001.len() / 2];
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(truncated)?;
+    assert!(ctx.validate().is_err());
+    Ok(())
+}
+
+#[derive(Debug)]
+struct PermissionDeniedReader;
+
+impl Read for PermissionDeniedReader {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied"))
+    }
+}
+
+#[test]
+fn stream_error_preserved() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<spng::Error>();
+
+    match Decoder::new(PermissionDeniedReader).read_info() {
+        Err(spng::Error::Stream(error)) => {
+            assert_eq!(io::ErrorKind::PermissionDenied, error.kind())
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn stream_panic_resumed() {
+    struct PanickingReader;
+    impl Read for PanickingReader {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            panic!("reader panicked")
+        }
+    }
+    let result = std::panic::catch_unwind(|| Decoder::new(PanickingReader).read_info().map(|_| ()));
+    let payload = result.expect_err("panic resumed after the FFI call");
+    assert_eq!(Some(&"reader panicked"), payload.downcast_ref::<&str>());
+}
+
+#[test]
+fn stream_error_source() {
+    use anyhow::Context;
+    let error = Decoder::new(PermissionDeniedReader)
+        .read_info()
+        .context("failed to decode png")
+        .unwrap_err();
+    let cause = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<io::Error>())
+        .expect("io error in chain");
+    assert_eq!(io::ErrorKind::PermissionDenied, cause.kind());
+    assert!(format!("{:#}", error).ends_with(": denied"));
+}
+
+#[test]
+fn get_srgb_intent() -> Result<(), Box<dyn std::error::Error>> {
+    use spng::RenderingIntent;
+    use std::convert::TryFrom;
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_011)?;
+    assert_eq!(1, ctx.get_srgb()?);
+    assert_eq!(
+        RenderingIntent::RelativeColorimetric,
+        ctx.get_srgb_intent()?
+    );
+    assert_eq!(Err(spng::Error::Srgb), RenderingIntent::try_from(4));
+    Ok(())
+}
+
+#[test]
+fn get_phys_dpi() -> Result<(), Box<dyn std::error::Error>> {
+    use spng::raw::chunk::Phys;
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_012)?;
+    let phys = ctx.get_phys()?;
+    assert_eq!((2835, 2835), phys.pixels_per_unit());
+    let (dpi_x, dpi_y) = phys.dpi().expect("unit is meters");
+    assert_eq!((72.0, 72.0), (dpi_x.round(), dpi_y.round()));
+    assert_eq!((2835, 2835), Phys::from_dpi(72.0, 72.0).pixels_per_unit());
+    assert_eq!(None, Phys::new(1, 2, 0).dpi());
+    Ok(())
+}
+
+#[test]
+fn get_bkgd() -> Result<(), Box<dyn std::error::Error>> {
+    let bkgd = |data| -> Result<spng::raw::chunk::Bkgd, spng::Error> {
+        let mut ctx = spng::raw::RawContext::new()?;
+        ctx.set_png_buffer(data)?;
+        ctx.get_bkgd()
+    };
+
+    let gray = bkgd(TEST_PNG_004)?;
+    assert_eq!(Some(128), gray.gray());
+    assert_eq!(None, gray.rgb());
+    assert_eq!(None, gray.palette_index());
+
+    let truecolor = bkgd(TEST_PNG_005)?;
+    assert_eq!(None, truecolor.gray());
+    assert_eq!(Some((10, 20, 30)), truecolor.rgb());
+    assert_eq!(None, truecolor.palette_index());
+
+    let indexed = bkgd(TEST_PNG_006)?;
+    assert_eq!(None, indexed.gray());
+    assert_eq!(None, indexed.rgb());
+    assert_eq!(Some(1), indexed.palette_index());
+    Ok(())
+}
+
+#[test]
+fn encode_001() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_001, spng::Format::Png)?;
+    let encoder = spng::Encoder::new(Vec::new(), out_info.width, out_info.height)
+        .with_color_type(out_info.color_type)
+        .with_bit_depth(out_info.bit_depth);
+    let mut writer = encoder.write_info()?;
+    writer.write_image_data(&data)?;
+    let encoded = writer.finish()?;
+
+    let (encoded_info, encoded_data) = spng::decode(encoded.as_slice(), spng::Format::Png)?;
+    assert_eq!(out_info, encoded_info);
+    assert_eq!(data, encoded_data);
+    Ok(())
+}
+
+#[test]
+fn encode_with_info() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_001, spng::Format::Png)?;
+    let mut writer = spng::Encoder::new(Vec::new(), 0, 0)
+        .with_info(&out_info)
+        .write_info()?;
+    writer.write_image_data(&data)?;
+    let encoded = writer.finish()?;
+
+    let (encoded_info, encoded_data) = spng::decode(encoded.as_slice(), spng::Format::Png)?;
+    assert_eq!(out_info, encoded_info);
+    assert_eq!(data, encoded_data);
+
+    let info = Decoder::new(TEST_PNG_001).read_info()?.info();
+    let mut writer = spng::Encoder::new(Vec::new(), 0, 0)
+        .with_info_from(&info)
+        .write_info()?;
+    writer.write_image_data(&data)?;
+    let encoded = writer.finish()?;
+    let reader = Decoder::new(encoded.as_slice()).read_info()?;
+    assert_eq!(info, reader.info());
+    Ok(())
+}
+
+#[test]
+fn encode_interlaced() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_001, spng::Format::Png)?;
+    let mut writer = spng::Encoder::new(Vec::new(), 0, 0)
+        .with_info(&out_info)
+        .with_interlace(InterlaceMethod::Adam7)
+        .write_info()?;
+    writer.write_image_data(&data)?;
+    let encoded = writer.finish()?;
+
+    let info = Decoder::new(encoded.as_slice()).read_info()?.info();
+    assert_eq!(InterlaceMethod::Adam7, info.interlace_method);
+    let (encoded_info, encoded_data) = spng::decode(encoded.as_slice(), spng::Format::Png)?;
+    assert_eq!(out_info, encoded_info);
+    assert_eq!(data, encoded_data);
     Ok(())
 }
 
+#[test]
+fn encode_compression_options() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_001, spng::Format::Png)?;
+    let encode = |compression_level, filter_choice| -> Result<Vec<u8>, spng::Error> {
+        let mut writer = spng::Encoder::new(Vec::new(), 0, 0)
+            .with_info(&out_info)
+            .with_compression_level(compression_level)
+            .with_filter_choice(filter_choice)
+            .write_info()?;
+        writer.write_image_data(&data)?;
+        writer.finish()
+    };
+    let stored = encode(0, spng::FilterChoice::empty())?;
+    let best = encode(9, spng::FilterChoice::ALL)?;
+    assert!(best.len() < stored.len());
+    for encoded in &[stored, best] {
+        let (_, encoded_data) = spng::decode(encoded.as_slice(), spng::Format::Png)?;
+        assert_eq!(data, encoded_data);
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_flags_recommended() -> Result<(), Box<dyn std::error::Error>> {
+    let trns = spng::raw::chunk::Trns {
+        gray: 0,
+        red: 10,
+        green: 20,
+        blue: 30,
+        n_type3_entries: 0,
+        type3_alpha: [0; 256],
+    };
+    let mut writer = spng::Encoder::new(Vec::new(), 2, 1)
+        .with_color_type(ColorType::Truecolor)
+        .with_bit_depth(BitDepth::Eight)
+        .with_chunks(spng::EncodeChunks::new().with_trns(trns))
+        .write_info()?;
+    writer.write_image_data(&[10, 20, 30, 40, 50, 60])?;
+    let encoded = writer.finish()?;
+
+    let mut reader = Decoder::new(encoded.as_slice())
+        .with_output_format(spng::Format::Rgba8)
+        .with_decode_flags(spng::DecodeFlags::recommended())
+        .read_info()?;
+    let mut out = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut out)?;
+    assert_eq!(vec![10, 20, 30, 0, 40, 50, 60, 255], out);
+    Ok(())
+}
+
+#[test]
+fn encode_001_raw_context() -> Result<(), Box<dyn std::error::Error>> {
+    let (ihdr, data) = {
+        let mut ctx = spng::raw::RawContext::new()?;
+        ctx.set_png_stream_reader(TEST_PNG_001)?;
+        let ihdr = ctx.get_ihdr()?;
+        let mut data = vec![0; ctx.decoded_image_size(spng::Format::Png)?];
+        ctx.decode_image(&mut data, spng::Format::Png, spng::DecodeFlags::empty())?;
+        (ihdr, data)
+    };
+
+    let mut encoded = Vec::new();
+    {
+        let mut ctx = spng::raw::RawContext::with_flags(spng::ContextFlags::ENCODER)?;
+        ctx.set_png_stream_writer(&mut encoded)?;
+        ctx.set_ihdr(&ihdr)?;
+        ctx.encode_image(&data, spng::Format::Png, spng::EncodeFlags::FINALIZE)?;
+    }
+
+    let (_, encoded_data) = spng::decode(encoded.as_slice(), spng::Format::Png)?;
+    assert_eq!(data, encoded_data);
+    Ok(())
+}
+
+#[test]
+fn encode_take_png_buffer() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_002, spng::Format::Rgba8)?;
+    let ihdr = spng::raw::chunk::Ihdr::from(&out_info);
+
+    let mut ctx = spng::raw::RawContext::<Vec<u8>>::with_flags(spng::ContextFlags::ENCODER)?;
+    ctx.set_option(spng::SpngOption::EncodeToBuffer, 1)?;
+    ctx.set_ihdr(&ihdr)?;
+    ctx.encode_image(&data, spng::Format::Png, spng::EncodeFlags::FINALIZE)?;
+    let encoded = ctx.take_png_buffer()?;
+    assert!(ctx.take_png_buffer().is_err());
+
+    let (_, decoded) = spng::decode(encoded.as_slice(), spng::Format::Rgba8)?;
+    assert_eq!(data, decoded);
+    Ok(())
+}
+
+#[test]
+fn encode_invalid_ihdr_methods() -> Result<(), Box<dyn std::error::Error>> {
+    let out_info = spng::OutputInfo {
+        width: 1,
+        height: 1,
+        color_type: spng::ColorType::Truecolor,
+        bit_depth: spng::BitDepth::Eight,
+        buffer_size: 3,
+    };
+    let ihdr = spng::raw::chunk::Ihdr::from(&out_info);
+    let mut ctx = spng::raw::RawContext::<Vec<u8>>::with_flags(spng::ContextFlags::ENCODER)?;
+    let invalid = [
+        (
+            spng::Error::CompressionMethod,
+            spng::raw::chunk::Ihdr {
+                compression_method: 1,
+                ..ihdr
+            },
+        ),
+        (
+            spng::Error::FilterMethod,
+            spng::raw::chunk::Ihdr {
+                filter_method: 64,
+                ..ihdr
+            },
+        ),
+        (
+            spng::Error::InterlaceMethod,
+            spng::raw::chunk::Ihdr {
+                interlace_method: 2,
+                ..ihdr
+            },
+        ),
+    ];
+    for (error, ihdr) in invalid {
+        assert_eq!(Err(error), ctx.set_ihdr(&ihdr));
+    }
+    ctx.set_ihdr(&ihdr)?;
+    Ok(())
+}
+
+#[test]
+fn encode_set_text_invalid() -> Result<(), Box<dyn std::error::Error>> {
+    let mut ctx = spng::raw::RawContext::<Vec<u8>>::with_flags(spng::ContextFlags::ENCODER)?;
+    let long_keyword = "k".repeat(80);
+    assert_eq!(
+        Err(spng::Error::TextKeyword),
+        ctx.set_text(&[(&long_keyword, "text")])
+    );
+    assert_eq!(Err(spng::Error::TextKeyword), ctx.set_text(&[("", "text")]));
+    assert_eq!(
+        Err(spng::Error::Text),
+        ctx.set_text(&[("Title", "\u{263a}")])
+    );
+    ctx.set_text(&[("Title", "caf\u{e9}")])?;
+    Ok(())
+}
+
+#[test]
+fn encode_crc_action() -> Result<(), Box<dyn std::error::Error>> {
+    let mut ctx = spng::raw::RawContext::<Vec<u8>>::with_flags(spng::ContextFlags::ENCODER)?;
+    assert_eq!(
+        Err(spng::Error::CtxType),
+        ctx.set_crc_action(spng::CrcAction::Use, spng::CrcAction::Use)
+    );
+    Ok(())
+}
+
+#[test]
+fn encode_chunks() -> Result<(), Box<dyn std::error::Error>> {
+    use spng::raw::chunk::{Bkgd, Plte, PlteEntry, Trns};
+    let entry = |red, green, blue| PlteEntry {
+        red,
+        green,
+        blue,
+        alpha: 255,
+    };
+    let plte = Plte::new(&[entry(255, 0, 0), entry(0, 0, 255)])?;
+    let mut type3_alpha = [255; 256];
+    type3_alpha[0] = 0;
+    let trns = Trns {
+        gray: 0,
+        red: 0,
+        green: 0,
+        blue: 0,
+        n_type3_entries: 2,
+        type3_alpha,
+    };
+    // deliberately out of order
+    let chunks = spng::EncodeChunks::new()
+        .with_bkgd(Bkgd::from_palette_index(1))
+        .with_text("Comment", "Encoded by spng")
+        .with_trns(trns)
+        .with_plte(plte);
+    let encoder = spng::Encoder::new(Vec::new(), 2, 1)
+        .with_color_type(ColorType::Indexed)
+        .with_bit_depth(BitDepth::Eight)
+        .with_chunks(chunks);
+    let mut writer = encoder.write_info()?;
+    writer.write_image_data(&[0, 1])?;
+    let encoded = writer.finish()?;
+
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(&encoded)?;
+    let mut out = vec![0; ctx.decoded_image_size(spng::Format::Rgba8)?];
+    ctx.decode_image(
+        &mut out,
+        spng::Format::Rgba8,
+        spng::DecodeFlags::TRANSPARENCY,
+    )?;
+    assert_eq!(vec![255, 0, 0, 0, 0, 0, 255, 255], out);
+    let plte = ctx.get_plte()?;
+    assert_eq!(2, plte.entries().len());
+    assert_eq!(255, plte.entries()[0].red);
+    let trns = ctx.get_trns()?;
+    assert_eq!(2, trns.n_type3_entries);
+    assert_eq!(0, trns.type3_alpha[0]);
+    assert_eq!(Some(1), ctx.get_bkgd()?.palette_index());
+    let text = ctx.get_text()?;
+    assert_eq!(1, text.len());
+    assert_eq!("Comment", text[0].keyword()?);
+    assert_eq!("Encoded by spng", text[0].text()?);
+    assert_eq!(spng::raw::chunk::TextType::Text, text[0].text_type()?);
+    Ok(())
+}
+
+#[test]
+fn encode_rows() -> Result<(), Box<dyn std::error::Error>> {
+    let fixture = common::generate(ColorType::Truecolor, BitDepth::Eight, 16, 16, false)?;
+    let (out_info, data) = spng::decode(fixture.png.as_slice(), spng::Format::Png)?;
+    let line_size = out_info.line_size();
+    let encoder = |output| {
+        spng::Encoder::new(output, out_info.width, out_info.height)
+            .with_color_type(out_info.color_type)
+            .with_bit_depth(out_info.bit_depth)
+    };
+
+    let mut writer = encoder(Vec::new()).write_info()?;
+    for row in data.chunks_exact(line_size) {
+        writer.write_row(row)?;
+    }
+    assert_eq!(out_info.height, writer.rows_written());
+    assert_eq!(Err(spng::Error::Oi), writer.write_row(&data[..line_size]));
+    let encoded = writer.finish()?;
+    let (_, encoded_data) = spng::decode(encoded.as_slice(), spng::Format::Png)?;
+    assert_eq!(data, encoded_data);
+
+    let mut writer = encoder(Vec::new()).write_info()?;
+    for row in data
+        .chunks_exact(line_size)
+        .take(out_info.height as usize / 2)
+    {
+        writer.write_row(row)?;
+    }
+    assert_eq!(out_info.height / 2, writer.rows_written());
+    assert_eq!(Err(spng::Error::NotFinal), writer.finish().map(|_| ()));
+
+    let mut writer = encoder(Vec::new())
+        .with_interlace(spng::InterlaceMethod::Adam7)
+        .write_info()?;
+    for row in data.chunks_exact(line_size) {
+        writer.write_row(row)?;
+    }
+    assert_eq!(out_info.height, writer.rows_written());
+    assert_eq!(Err(spng::Error::Oi), writer.write_row(&data[..line_size]));
+    let encoded = writer.finish()?;
+    let mut reader = spng::Decoder::new(encoded.as_slice()).read_info()?;
+    assert_eq!(spng::InterlaceMethod::Adam7, reader.info().interlace_method);
+    let mut encoded_data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut encoded_data)?;
+    assert_eq!(data, encoded_data);
+    Ok(())
+}
+
+#[test]
+fn encode_limits() {
+    use spng::{Error, LimitKind};
+    let limits = spng::Limits {
+        max_width: 1024,
+        max_height: 1024,
+        max_pixels: 1024 * 512,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let wide = spng::Encoder::new(&mut output, 1025, 1).with_limits(limits);
+    let error = wide.write_info().map(|_| ()).unwrap_err();
+    assert_eq!(
+        Error::LimitsExceeded {
+            kind: LimitKind::Width,
+            value: 1025,
+            limit: 1024
+        },
+        error
+    );
+    assert_eq!(spng::ErrorCategory::Limits, error.category());
+    let tall = spng::Encoder::new(&mut output, 1, 1_000_000).with_limits(limits);
+    let error = tall.write_info().map(|_| ()).unwrap_err();
+    assert!(matches!(
+        error,
+        Error::LimitsExceeded {
+            kind: LimitKind::Height,
+            ..
+        }
+    ));
+    let large = spng::Encoder::new(&mut output, 1024, 1024).with_limits(limits);
+    let error = large.write_info().map(|_| ()).unwrap_err();
+    assert!(matches!(
+        error,
+        Error::LimitsExceeded {
+            kind: LimitKind::Pixels,
+            ..
+        }
+    ));
+    assert!(output.is_empty());
+}
+
+#[test]
+fn decode_limits() {
+    use spng::{Error, LimitKind, Limits};
+    let decode = |limits| {
+        Decoder::new(TEST_PNG_001)
+            .with_output_format(spng::Format::Rgba8)
+            .with_limits(limits)
+            .read_info()
+            .map(|_| ())
+    };
+    let exceeded = |kind, value, limit| Err(Error::LimitsExceeded { kind, value, limit });
+
+    let width = Limits {
+        max_width: 299,
+        ..Default::default()
+    };
+    assert_eq!(exceeded(LimitKind::Width, 300, 299), decode(width));
+    let height = Limits {
+        max_height: 299,
+        ..Default::default()
+    };
+    assert_eq!(exceeded(LimitKind::Height, 300, 299), decode(height));
+    let pixels = Limits {
+        max_pixels: 300 * 300 - 1,
+        ..Default::default()
+    };
+    assert_eq!(
+        exceeded(LimitKind::Pixels, 300 * 300, 300 * 300 - 1),
+        decode(pixels)
+    );
+    let alloc_bytes = Limits {
+        max_alloc_bytes: 300 * 300 * 4 - 1,
+        ..Default::default()
+    };
+    assert_eq!(
+        exceeded(LimitKind::AllocBytes, 300 * 300 * 4, 300 * 300 * 4 - 1),
+        decode(alloc_bytes)
+    );
+    let exact = Limits {
+        max_width: 300,
+        max_height: 300,
+        max_pixels: 300 * 300,
+        max_alloc_bytes: 300 * 300 * 4,
+        max_input_bytes: u64::MAX,
+    };
+    assert_eq!(Ok(()), decode(exact));
+
+    // test-022.png is a 2147483647x2147483647 RGBA16 header whose buffer size overflows
+    let huge = Decoder::new(TEST_PNG_022)
+        .with_limits(Limits {
+            max_width: 300,
+            ..Default::default()
+        })
+        .read_info()
+        .map(|_| ());
+    assert_eq!(exceeded(LimitKind::Width, 0x7fff_ffff, 300), huge);
+}
+
+#[test]
+fn decode_debug_assert_full_write() -> Result<(), Box<dyn std::error::Error>> {
+    for png in [TEST_PNG_001, TEST_PNG_002] {
+        let mut reader = Decoder::new(png)
+            .with_output_format(spng::Format::Rgba8)
+            .with_debug_assert_full_write(true)
+            .read_info()?;
+        let mut out = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut out)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_composite_over() -> Result<(), Box<dyn std::error::Error>> {
+    use spng::{raw::chunk::Bkgd, Background, EncodeChunks, Format};
+    // Half, fully and not transparent RGBA8 pixels
+    let pixels = [200, 100, 0, 128, 10, 20, 30, 0, 10, 20, 30, 255];
+    let encode = |chunks: EncodeChunks| -> Result<Vec<u8>, spng::Error> {
+        let mut writer = spng::Encoder::new(Vec::new(), 3, 1)
+            .with_chunks(chunks)
+            .write_info()?;
+        writer.write_image_data(&pixels)?;
+        writer.finish()
+    };
+    let decode = |png: &[u8], background| -> Result<Vec<u8>, spng::Error> {
+        let mut reader = Decoder::new(png)
+            .with_output_format(Format::Rgb8)
+            .with_composite_over(background)
+            .read_info()?;
+        let mut out = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut out)?;
+        Ok(out)
+    };
+    let png = encode(EncodeChunks::new())?;
+
+    let black = decode(&png, Some(Background::Rgb([0; 3])))?;
+    assert_eq!(vec![100, 50, 0, 0, 0, 0, 10, 20, 30], black);
+    let white = decode(&png, Some(Background::Rgb([0xffff; 3])))?;
+    assert_eq!(vec![227, 177, 127, 255, 255, 255, 10, 20, 30], white);
+
+    // Without compositing, or a `bKGD` chunk to composite over, the alpha is discarded
+    let discarded = vec![200, 100, 0, 10, 20, 30, 10, 20, 30];
+    assert_eq!(discarded, decode(&png, None)?);
+    assert_eq!(discarded, decode(&png, Some(Background::Bkgd))?);
+
+    let png = encode(EncodeChunks::new().with_bkgd(Bkgd::from_rgb(255, 255, 255)))?;
+    assert_eq!(white, decode(&png, Some(Background::Bkgd))?);
+    Ok(())
+}
+
+#[test]
+fn decode_max_input_bytes() {
+    use spng::{Error, LimitKind, Limits};
+    let decode = |max_input_bytes| {
+        let limits = Limits {
+            max_input_bytes,
+            ..Default::default()
+        };
+        Decoder::new(TEST_PNG_001)
+            .with_output_format(spng::Format::Rgba8)
+            .with_limits(limits)
+            .read_info()
+            .and_then(|mut reader| {
+                let mut out = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut out)
+            })
+            .map(|_| ())
+    };
+    let size = TEST_PNG_001.len() as u64;
+    assert!(matches!(
+        decode(size - 1),
+        Err(Error::LimitsExceeded {
+            kind: LimitKind::InputBytes,
+            limit,
+            ..
+        }) if limit == size - 1
+    ));
+    assert!(matches!(
+        decode(16),
+        Err(Error::LimitsExceeded {
+            kind: LimitKind::InputBytes,
+            ..
+        })
+    ));
+    assert_eq!(Ok(()), decode(size));
+}
+
+#[test]
+fn encode_drop_without_finish() -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = spng::Encoder::new(Vec::new(), 1, 1).write_info()?;
+    writer.write_image_data(&[0, 0, 0, 255])?;
+    drop(writer);
+
+    let writer = spng::Encoder::new(Vec::new(), 1, 1).write_info()?;
+    drop(writer);
+
+    let writer = spng::Encoder::new(Vec::new(), 1, 1).write_info()?;
+    assert_eq!(Err(spng::Error::NotFinal), writer.finish().map(|_| ()));
+    Ok(())
+}
+
+#[test]
+fn get_unknown_chunks_owned() -> Result<(), Box<dyn std::error::Error>> {
+    let chunks = {
+        let mut ctx = spng::raw::RawContext::new()?;
+        ctx.set_option(spng::SpngOption::KeepUnknownChunks, 1)?;
+        ctx.set_png_buffer(TEST_PNG_007)?;
+        let mut data = vec![0; ctx.decoded_image_size(spng::Format::Png)?];
+        ctx.decode_image(&mut data, spng::Format::Png, spng::DecodeFlags::empty())?;
+        ctx.get_unknown_chunks_owned()?
+    };
+    assert_eq!(2, chunks.len());
+    assert_eq!("teSt", chunks[0].type_str()?);
+    assert_eq!(b"hello", &chunks[0].data[..]);
+    assert_eq!(b"world", &chunks[1].data[..]);
+    Ok(())
+}
+
+#[test]
+fn debug_omits_pointers() -> Result<(), Box<dyn std::error::Error>> {
+    let decoder = Decoder::new(TEST_PNG_001).with_output_format(spng::Format::Rgba8);
+    let decoder_debug = format!("{:?}", decoder);
+    assert!(decoder_debug.contains("Rgba8"));
+    let reader = decoder.read_info()?;
+    let reader_debug = format!("{:?}", reader);
+    assert!(reader_debug.contains("has_stream: true"));
+    for debug in [decoder_debug, reader_debug].iter() {
+        assert!(!debug.contains("0x"), "{}", debug);
+    }
+    Ok(())
+}
+
+#[test]
+fn as_str_round_trip() {
+    use spng::Format;
+    use ColorType::*;
+    for &color_type in &[
+        Grayscale,
+        Truecolor,
+        Indexed,
+        GrayscaleAlpha,
+        TruecolorAlpha,
+    ] {
+        assert_eq!(Ok(color_type), color_type.as_str().parse());
+    }
+    for &bit_depth in &[
+        BitDepth::One,
+        BitDepth::Two,
+        BitDepth::Four,
+        BitDepth::Eight,
+        BitDepth::Sixteen,
+    ] {
+        assert_eq!(Ok(bit_depth), bit_depth.as_str().parse());
+    }
+    for &format in &[
+        Format::Rgba8,
+        Format::Rgba16,
+        Format::Rgb8,
+        Format::G8,
+        Format::Ga8,
+        Format::Ga16,
+        Format::Png,
+        Format::Raw,
+        Format::Auto,
+    ] {
+        assert_eq!(Ok(format), format.as_str().parse());
+    }
+    assert_eq!("truecolor-alpha", TruecolorAlpha.as_str());
+    assert_eq!("16", BitDepth::Sixteen.as_str());
+    assert_eq!("rgba16", Format::Rgba16.as_str());
+    assert_eq!(Err(spng::Error::Fmt), "rgba32".parse::<Format>());
+}
+
+#[test]
+fn format_bytes_per_pixel() -> Result<(), Box<dyn std::error::Error>> {
+    use spng::Format;
+    for &(format, bytes_per_pixel) in &[
+        (Format::Rgba8, 4),
+        (Format::Rgba16, 8),
+        (Format::Rgb8, 3),
+        (Format::G8, 1),
+        (Format::Ga8, 2),
+        (Format::Ga16, 4),
+    ] {
+        assert_eq!(Some(bytes_per_pixel), format.bytes_per_pixel());
+    }
+    for &format in &[Format::Png, Format::Raw, Format::Auto] {
+        assert_eq!(None, format.bytes_per_pixel());
+    }
+
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_001)?;
+    let ihdr = ctx.get_ihdr()?;
+    let pixels = ihdr.width as usize * ihdr.height as usize;
+    for &format in &[Format::Rgba8, Format::Rgba16, Format::Rgb8] {
+        let bytes_per_pixel = format.bytes_per_pixel().unwrap();
+        assert_eq!(pixels * bytes_per_pixel, ctx.decoded_image_size(format)?);
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_partial_truncated() -> Result<(), Box<dyn std::error::Error>> {
+    // test-013 is a 16x16 8-bit grayscale image with each row flushed to its own IDAT chunk.
+    // Cut the file after the fourth IDAT so only the first rows can be decoded.
+    let truncated = &TEST_PNG_013[..179];
+    let partial = spng::decode_partial(truncated, spng::Format::G8)?;
+    assert!(!partial.is_complete());
+    assert!(partial.rows_decoded > 0 && partial.rows_decoded <= 4);
+    let (_, full) = spng::decode(TEST_PNG_013, spng::Format::G8)?;
+    let line_size = partial.image.info.line_size();
+    let decoded = partial.rows_decoded as usize * line_size;
+    assert_eq!(&full[..decoded], &partial.image.data[..decoded]);
+    assert!(partial.image.data[decoded..].iter().all(|&b| b == 0));
+
+    let complete = spng::decode_partial(TEST_PNG_013, spng::Format::G8)?;
+    assert!(complete.is_complete());
+    assert_eq!(full, complete.image.data);
+    Ok(())
+}
+
+#[test]
+fn next_frame_partial() -> Result<(), Box<dyn std::error::Error>> {
+    let decoder = Decoder::new(TEST_PNG_001).with_output_format(spng::Format::Rgba8);
+    let mut reader = decoder.read_info()?;
+    let mut out = vec![0; reader.output_buffer_size()];
+    let (info, rows_decoded) = reader.next_frame_partial(&mut out)?;
+    assert_eq!(info.height, rows_decoded);
+    let (_, expected) = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    assert_eq!(expected, out);
+    Ok(())
+}
+
+#[test]
+fn has_chunk() -> Result<(), Box<dyn std::error::Error>> {
+    use spng::ChunkType;
+    let reader = Decoder::new(TEST_PNG_001).read_info()?;
+    assert!(reader.has_chunk(ChunkType::Text));
+    assert!(reader.has_chunk(ChunkType::Srgb));
+    assert!(reader.has_chunk(ChunkType::Time));
+    assert!(!reader.has_chunk(ChunkType::Iccp));
+    assert!(!reader.has_chunk(ChunkType::Plte));
+    Ok(())
+}
+
+#[test]
+fn decode_rows_scatter() -> Result<(), Box<dyn std::error::Error>> {
+    let interlaced = common::generate(ColorType::Grayscale, BitDepth::Eight, 1, 1, true)?;
+    for png in [TEST_PNG_001, interlaced.png.as_slice()] {
+        let (expected_info, expected) = spng::decode(png, spng::Format::Rgba8)?;
+        let decoder = Decoder::new(png).with_output_format(spng::Format::Rgba8);
+        let mut reader = decoder.read_info()?;
+        let line_size = reader.output_buffer_size() / reader.info().height as usize;
+        let mut rows = vec![vec![0; line_size]; reader.info().height as usize];
+        let mut row_slices: Vec<&mut [u8]> = rows.iter_mut().map(Vec::as_mut_slice).collect();
+        let info = reader.decode_rows_scatter(&mut row_slices)?;
+        assert_eq!(expected_info, info);
+        assert_eq!(expected, rows.concat());
+    }
+
+    let mut reader = Decoder::new(TEST_PNG_001).read_info()?;
+    assert_eq!(
+        Err(spng::Error::Bufsiz),
+        reader.decode_rows_scatter(&mut [])
+    );
+    Ok(())
+}
+
+// The WASI test runner doesn't expose the file system
+#[cfg(not(target_os = "wasi"))]
+#[test]
+fn decode_path() -> Result<(), Box<dyn std::error::Error>> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test-001.png");
+    let expected = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    assert_eq!(expected, spng::decode_path(path, spng::Format::Rgba8)?);
+
+    let mut reader = Decoder::open(path)?
+        .with_output_format(spng::Format::Rgba8)
+        .read_info()?;
+    let mut out = vec![0; reader.output_buffer_size()];
+    assert_eq!(expected.0, reader.next_frame(&mut out)?);
+    assert_eq!(expected.1, out);
+
+    let missing = spng::decode_path("missing.png", spng::Format::Rgba8);
+    assert!(matches!(missing, Err(spng::Error::Stream(_))));
+    Ok(())
+}
+
+#[test]
+fn encode_short_writes() -> Result<(), Box<dyn std::error::Error>> {
+    /// Accepts one byte per `write` call, like a congested pipe
+    struct OneByte {
+        data: Vec<u8>,
+        flushed: bool,
+    }
+
+    impl io::Write for OneByte {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.extend_from_slice(&buf[..buf.len().min(1)]);
+            Ok(buf.len().min(1))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed = true;
+            Ok(())
+        }
+    }
+
+    let (out_info, data) = spng::decode(TEST_PNG_002, spng::Format::Png)?;
+    let sink = OneByte {
+        data: Vec::new(),
+        flushed: false,
+    };
+    let mut writer = spng::Encoder::new(sink, 0, 0)
+        .with_info(&out_info)
+        .write_info()?;
+    writer.write_image_data(&data)?;
+    let sink = writer.finish()?;
+    assert!(sink.flushed);
+
+    let mut writer = spng::Encoder::new(Vec::new(), 0, 0)
+        .with_info(&out_info)
+        .write_info()?;
+    writer.write_image_data(&data)?;
+    assert_eq!(writer.finish()?, sink.data);
+    Ok(())
+}
+
+// The WASI test runner doesn't expose the file system
+#[cfg(not(target_os = "wasi"))]
+#[test]
+fn encode_create() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    let path = std::env::temp_dir().join("spng_encode_create.png");
+    let mut writer = spng::Encoder::create(&path, 0, 0)?
+        .with_info(&out_info)
+        .write_info()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    let decoded = spng::decode_path(&path, spng::Format::Rgba8);
+    std::fs::remove_file(&path)?;
+    assert_eq!((out_info, data), decoded?);
+    Ok(())
+}
+
+#[test]
+fn set_png_buffer_signature() -> Result<(), Box<dyn std::error::Error>> {
+    let jpeg: &[u8] = &[
+        0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00,
+    ];
+    for buf in [&[][..], &TEST_PNG_001[..4], jpeg] {
+        let mut ctx = spng::raw::RawContext::new()?;
+        assert_eq!(Err(spng::Error::Signature), ctx.set_png_buffer(buf));
+        let mut ctx = spng::raw::RawContext::new()?;
+        assert_eq!(
+            Err(spng::Error::Signature),
+            ctx.set_png_owned_buffer(buf.to_vec())
+        );
+        assert_eq!(
+            Some(spng::Error::Signature),
+            spng::decode_bytes(buf, spng::Format::Rgba8).err()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_full() -> Result<(), Box<dyn std::error::Error>> {
+    let image = spng::decode_full(TEST_PNG_001, spng::Format::Rgba8)?;
+    assert_eq!(image.info.buffer_size, image.data.len());
+    assert_eq!(
+        vec![("Comment".to_owned(), "Created with GIMP".to_owned())],
+        image.text
+    );
+    assert_eq!(Some(spng::RenderingIntent::Perceptual), image.srgb_intent);
+    let (dpi_x, dpi_y) = image.dpi.expect("pHYs in test image");
+    assert_eq!(72, dpi_x.round() as u32);
+    assert_eq!(72, dpi_y.round() as u32);
+    let time = image.time.expect("tIME in test image");
+    assert_eq!((2009, 7, 24), (time.year, time.month, time.day));
+
+    let image = spng::decode_full(TEST_PNG_019, spng::Format::Rgb8)?;
+    assert_eq!(Some(1.0), image.gamma);
+    assert!(image.text.is_empty());
+    Ok(())
+}
+
+#[test]
+fn is_png() -> Result<(), Box<dyn std::error::Error>> {
+    let jpeg: &[u8] = &[
+        0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00,
+    ];
+    assert!(spng::is_png(TEST_PNG_001));
+    assert!(spng::is_png(&TEST_PNG_001[..8]));
+    assert!(!spng::is_png(&TEST_PNG_001[..7]));
+    assert!(!spng::is_png(&[]));
+    assert!(!spng::is_png(jpeg));
+
+    assert!(spng::detect(TEST_PNG_001)?);
+    assert!(!spng::detect(&TEST_PNG_001[..7])?);
+    assert!(!spng::detect(jpeg)?);
+    Ok(())
+}
+
+#[test]
+fn validate_structure() {
+    for png in [TEST_PNG_001, TEST_PNG_002, TEST_PNG_006, TEST_PNG_018] {
+        assert_eq!(Ok(()), spng::validate_structure(png));
+    }
+
+    let truncated = &TEST_PNG_001[..TEST_PNG_001.len() - 20];
+    assert_eq!(Err(spng::Error::IoEof), spng::validate_structure(truncated));
+
+    // Corrupt the IHDR width, which is covered by the CRC
+    let mut bad_crc = TEST_PNG_001.to_vec();
+    bad_crc[16] ^= 0xff;
+    assert_eq!(
+        Err(spng::Error::ChunkCrc),
+        spng::validate_structure(&bad_crc)
+    );
+    assert!(spng::decode_bytes(&bad_crc, spng::Format::Rgba8).is_err());
+
+    assert_eq!(
+        Err(spng::Error::Signature),
+        spng::validate_structure(&TEST_PNG_001[1..])
+    );
+}
+
+#[test]
+fn decode_lenient() -> Result<(), Box<dyn std::error::Error>> {
+    let decode = |png, lenient| -> Result<Vec<u8>, spng::Error> {
+        let decoder = Decoder::new(png)
+            .with_output_format(spng::Format::Rgb8)
+            .with_lenient(lenient);
+        let mut reader = decoder.read_info()?;
+        let mut out = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut out)?;
+        Ok(out)
+    };
+    let expected = vec![10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 120];
+
+    // test-020 is a 2x2 RGB8 image with a corrupt IDAT CRC and zlib checksum
+    assert!(decode(TEST_PNG_020, false).is_err());
+    assert_eq!(expected, decode(TEST_PNG_020, true)?);
+
+    // test-021 is the same image with valid checksums and a gAMA chunk after IDAT. Lenient
+    // decoding has no say in chunk ordering, so the outcome is the same either way.
+    let strict = decode(TEST_PNG_021, false);
+    assert_eq!(strict, decode(TEST_PNG_021, true));
+    if let Ok(out) = strict {
+        assert_eq!(expected, out);
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_auto_format() -> Result<(), Box<dyn std::error::Error>> {
+    use spng::Format;
+    let auto = |png| -> Result<Format, spng::Error> {
+        let reader = Decoder::new(png)
+            .with_output_format(Format::Auto)
+            .read_info()?;
+        Ok(reader.output_format())
+    };
+    // Grayscale, truecolor, indexed, RGBA8 and RGBA16
+    assert_eq!(Format::G8, auto(TEST_PNG_004)?);
+    assert_eq!(Format::Rgb8, auto(TEST_PNG_005)?);
+    assert_eq!(Format::Rgb8, auto(TEST_PNG_006)?);
+    assert_eq!(Format::Rgba8, auto(TEST_PNG_001)?);
+    let rgba16 = common::generate(ColorType::TruecolorAlpha, BitDepth::Sixteen, 3, 2, false)?;
+    assert_eq!(Format::Rgba16, auto(rgba16.png.as_slice())?);
+
+    let (info, data) = spng::decode(TEST_PNG_006, Format::Auto)?;
+    assert_eq!((info, data), spng::decode(TEST_PNG_006, Format::Rgb8)?);
+    let image = spng::decode_bytes(TEST_PNG_004, Format::Auto)?;
+    assert_eq!(ColorType::Grayscale, image.info.color_type);
+
+    let mut reader = Decoder::new(rgba16.png.as_slice()).read_info()?;
+    let mut out = vec![0; 3 * 2 * 8];
+    let info = reader.decode_as(&mut out, Format::Auto, spng::DecodeFlags::empty())?;
+    assert_eq!(ColorType::TruecolorAlpha, info.color_type);
+    assert_eq!(BitDepth::Sixteen, info.bit_depth);
+
+    assert_eq!(
+        Format::Ga8,
+        Format::for_image(ColorType::Grayscale, BitDepth::Four, true)
+    );
+    assert_eq!(
+        Format::Rgba8,
+        Format::for_image(ColorType::Indexed, BitDepth::Eight, true)
+    );
+    assert_eq!(
+        Format::Rgba16,
+        Format::for_image(ColorType::Truecolor, BitDepth::Sixteen, false)
+    );
+    Ok(())
+}
+
+#[test]
+fn decode_progress() -> Result<(), Box<dyn std::error::Error>> {
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_005)?;
+    let mut out = vec![0; ctx.decoded_image_size(spng::Format::Rgba8)?];
+    ctx.decode_image(&mut out, spng::Format::Rgba8, spng::DecodeFlags::empty())?;
+    // Every row was decoded
+    assert!(matches!(ctx.get_row_info(), Err(spng::Error::Oi)));
+
+    let mut reader = Decoder::new(TEST_PNG_005).read_info()?;
+    let mut rows = reader.rows()?;
+    assert_eq!((0, 4), rows.progress());
+    rows.next_row().expect("first row")?;
+    assert_eq!((1, 4), rows.progress());
+    while let Some(row) = rows.next_row() {
+        row?;
+    }
+    assert_eq!((4, 4), rows.progress());
+    Ok(())
+}
+
+#[test]
+fn next_frame_progressive() -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = Decoder::new(TEST_PNG_002)
+        .with_decode_flags(spng::DecodeFlags::PROGRESSIVE)
+        .read_info()?;
+    let mut out = vec![0; reader.output_buffer_size()];
+    assert_eq!(Err(spng::Error::OpState), reader.next_frame(&mut out));
+    // The progressive API still works
+    let mut rows = reader.rows()?;
+    while let Some(row) = rows.next_row() {
+        row?;
+    }
+    Ok(())
+}
+
+#[test]
+fn next_frame_u16() -> Result<(), Box<dyn std::error::Error>> {
+    // The generated samples have distinct high and low bytes
+    let fixture = common::generate(ColorType::TruecolorAlpha, BitDepth::Sixteen, 3, 2, false)?;
+    let first = u16::from_be_bytes([fixture.data[0], fixture.data[1]]);
+    for format in [spng::Format::Rgba16, spng::Format::Png] {
+        let mut reader = Decoder::new(fixture.png.as_slice())
+            .with_output_format(format)
+            .read_info()?;
+        let mut out = vec![0u16; reader.output_buffer_size() / 2];
+        let info = reader.next_frame_u16(&mut out)?;
+        assert_eq!(3 * 2 * 4, out.len());
+        assert_eq!(info.buffer_size, out.len() * 2);
+        assert_eq!(first, out[0]);
+    }
+
+    let mut reader = Decoder::new(fixture.png.as_slice())
+        .with_output_format(spng::Format::Rgba8)
+        .read_info()?;
+    let mut out = vec![0u16; reader.output_buffer_size()];
+    assert_eq!(Err(spng::Error::Fmt), reader.next_frame_u16(&mut out));
+    let mut reader = Decoder::new(fixture.png.as_slice())
+        .with_output_format(spng::Format::Raw)
+        .read_info()?;
+    assert_eq!(Err(spng::Error::Fmt), reader.next_frame_u16(&mut out));
+    Ok(())
+}
+
+#[test]
+fn encode_rgba16_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    // The generated samples have distinct high and low bytes
+    let fixture = common::generate(ColorType::TruecolorAlpha, BitDepth::Sixteen, 3, 2, false)?;
+    let (out_info, rgba16) = spng::decode(fixture.png.as_slice(), spng::Format::Rgba16)?;
+    let mut png = Vec::new();
+    let mut writer = spng::Encoder::new(&mut png, 0, 0)
+        .with_info(&out_info)
+        .with_source_format(spng::Format::Rgba16)
+        .write_info()?;
+    writer.write_image_data(&rgba16)?;
+    writer.finish()?;
+
+    // The encoded samples are big-endian, like the original
+    let (_, raw) = spng::decode(png.as_slice(), spng::Format::Raw)?;
+    assert_eq!(fixture.data, raw);
+    let (_, decoded) = spng::decode(png.as_slice(), spng::Format::Rgba16)?;
+    assert_eq!(rgba16, decoded);
+    let first = u16::from_be_bytes([fixture.data[0], fixture.data[1]]);
+    assert_eq!(first, u16::from_ne_bytes([decoded[0], decoded[1]]));
+
+    let encoder = spng::Encoder::new(Vec::new(), 3, 2).with_source_format(spng::Format::Rgba16);
+    assert_eq!(Err(spng::Error::Fmt), encoder.write_info().map(|_| ()));
+    Ok(())
+}
+
+#[test]
+fn chunk_limits_typed() -> Result<(), Box<dyn std::error::Error>> {
+    use spng::raw::ChunkLimits;
+    let mut ctx = spng::raw::RawContext::<&[u8]>::new()?;
+    assert_eq!(ChunkLimits::default(), ctx.get_chunk_limits_typed()?);
+    assert_eq!((i32::MAX as usize, usize::MAX), ctx.get_chunk_limits()?);
+
+    let limits = ChunkLimits {
+        chunk_size: Some(1 << 20),
+        cache_size: Some(1 << 24),
+    };
+    ctx.set_chunk_limits_typed(limits)?;
+    assert_eq!(limits, ctx.get_chunk_limits_typed()?);
+    assert_eq!((1 << 20, 1 << 24), ctx.get_chunk_limits()?);
+
+    let limits = ChunkLimits {
+        chunk_size: Some(1 << 20),
+        cache_size: None,
+    };
+    ctx.set_chunk_limits_typed(limits)?;
+    assert_eq!(limits, ctx.get_chunk_limits_typed()?);
+    Ok(())
+}
+
+#[test]
+fn decoded_image_crop() -> Result<(), Box<dyn std::error::Error>> {
+    // test-005.png is a 4x4 8-bit RGB image
+    let (out_info, data) = spng::decode(TEST_PNG_005, spng::Format::Png)?;
+    let image = spng::DecodedImage::new(out_info, data);
+    let cropped = image.crop(1, 2, 2, 2).expect("crop in bounds");
+    assert_eq!(2, cropped.info.width);
+    assert_eq!(2, cropped.info.height);
+    assert_eq!(12, cropped.info.buffer_size);
+    let expected: Vec<u8> = [&image.data[27..33], &image.data[39..45]].concat();
+    assert_eq!(expected, cropped.data);
+
+    assert_eq!(Some(image.clone()), image.crop(0, 0, 4, 4));
+    assert_eq!(None, image.crop(3, 3, 2, 2));
+    assert_eq!(None, image.crop(0, 0, 0, 1));
+    assert_eq!(None, image.crop(u32::MAX, 0, 2, 2));
+
+    let g1 = common::generate(ColorType::Grayscale, BitDepth::One, 10, 2, false)?;
+    let (out_info, data) = spng::decode(g1.png.as_slice(), spng::Format::Png)?;
+    assert_eq!(
+        None,
+        spng::DecodedImage::new(out_info, data).crop(0, 0, 1, 1)
+    );
+    Ok(())
+}
+
+#[test]
+fn decoded_image_resize() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    let image = spng::DecodedImage::new(out_info, data);
+    let pixel = |image: &spng::DecodedImage, x: usize, y: usize| {
+        let start = y * image.info.line_size() + x * 4;
+        image.data[start..start + 4].to_vec()
+    };
+
+    let nearest = image.resize_nearest(150, 150).expect("8-bit image");
+    assert_eq!((150, 150), (nearest.info.width, nearest.info.height));
+    assert_eq!(150 * 150 * 4, nearest.data.len());
+    assert_eq!(nearest.data.len(), nearest.info.buffer_size);
+    assert_eq!(pixel(&image, 41, 201), pixel(&nearest, 20, 100));
+
+    // Halving samples each output pixel halfway between a 2x2 block of source pixels
+    let bilinear = image.resize_bilinear(150, 150).expect("8-bit image");
+    assert_eq!((150, 150), (bilinear.info.width, bilinear.info.height));
+    assert_eq!(150 * 150 * 4, bilinear.data.len());
+    let block = [(40, 200), (41, 200), (40, 201), (41, 201)];
+    let average: Vec<u8> = (0..4)
+        .map(|c| {
+            let sum: u32 = block
+                .iter()
+                .map(|&(x, y)| pixel(&image, x, y)[c] as u32)
+                .sum();
+            ((sum + 2) / 4) as u8
+        })
+        .collect();
+    assert_eq!(average, pixel(&bilinear, 20, 100));
+
+    assert_eq!(Some(image.clone()), image.resize_nearest(300, 300));
+    assert_eq!(Some(image.clone()), image.resize_bilinear(300, 300));
+    assert_eq!(None, image.resize_nearest(0, 150));
+    assert_eq!(None, image.resize_bilinear(150, 0));
+    Ok(())
+}
+
+#[test]
+fn transcode() -> Result<(), Box<dyn std::error::Error>> {
+    let encoder = spng::Encoder::new(Vec::new(), 0, 0)
+        .with_compression_level(9)
+        .with_filter_choice(spng::FilterChoice::PAETH);
+    let transcoder = spng::Transcoder::new(TEST_PNG_002, encoder, spng::Format::Rgb8);
+    let (out_info, png) = transcoder.run()?;
+    assert_eq!(ColorType::Truecolor, out_info.color_type);
+
+    let (expected_info, expected) = spng::decode(TEST_PNG_002, spng::Format::Rgb8)?;
+    assert_eq!(expected_info, out_info);
+    let (info, data) = spng::decode(png.as_slice(), spng::Format::Png)?;
+    assert_eq!(expected_info, info);
+    assert_eq!(expected, data);
+
+    // Interlaced input is buffered, the output is written with the encoder's interlacing
+    let fixture = common::generate(ColorType::TruecolorAlpha, BitDepth::Eight, 7, 5, true)?;
+    for &interlace_method in &[InterlaceMethod::None, InterlaceMethod::Adam7] {
+        let encoder = spng::Encoder::new(Vec::new(), 0, 0).with_interlace(interlace_method);
+        let transcoder =
+            spng::Transcoder::new(fixture.png.as_slice(), encoder, spng::Format::Rgba8);
+        let (_, png) = transcoder.run()?;
+        let mut reader = Decoder::new(png.as_slice()).read_info()?;
+        assert_eq!(interlace_method, reader.info().interlace_method);
+        let mut data = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut data)?;
+        assert_eq!(fixture.data, data);
+    }
+    Ok(())
+}
+
+#[test]
+fn reader_info_cached() -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = Decoder::new(TEST_PNG_001).read_info()?;
+    let info = reader.info();
+    assert_eq!(info, reader.info());
+    assert_eq!(300, info.width);
+    assert_eq!(ColorType::TruecolorAlpha, info.color_type);
+    let mut out = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut out)?;
+    assert_eq!(info, reader.info());
+    Ok(())
+}
+
+#[test]
+fn decode_apng_default_image() -> Result<(), Box<dyn std::error::Error>> {
+    // test-018.png is a 2x2 APNG with a red default image followed by a blue fdAT frame
+    let (out_info, data) = spng::decode(TEST_PNG_018, spng::Format::Rgba8)?;
+    assert_eq!(2, out_info.width);
+    assert_eq!([0xff, 0x00, 0x00, 0xff].repeat(4), data);
+    Ok(())
+}
+
+#[test]
+fn decode_gamma() -> Result<(), Box<dyn std::error::Error>> {
+    // test-019.png is a 4x1 RGB8 gray ramp of 0, 64, 128, 255 with a gAMA of 1.0
+    let decode = |decode_flags| -> Result<Vec<u8>, spng::Error> {
+        let mut reader = Decoder::new(TEST_PNG_019)
+            .with_output_format(spng::Format::Rgb8)
+            .with_decode_flags(decode_flags)
+            .read_info()?;
+        let mut out = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut out)?;
+        Ok(out.chunks(3).map(|pixel| pixel[0]).collect())
+    };
+    assert_eq!(vec![0, 64, 128, 255], decode(spng::DecodeFlags::empty())?);
+    // Corrected for a display gamma of 2.2: 255 * (sample / 255) ^ (1 / 2.2)
+    let corrected = decode(spng::DecodeFlags::GAMMA)?;
+    let expected = [0, 136, 186, 255];
+    for (&sample, &expected) in corrected.iter().zip(&expected) {
+        assert!((sample as i32 - expected).abs() <= 1, "{:?}", corrected);
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_with_arena() -> Result<(), Box<dyn std::error::Error>> {
+    let (expected_info, expected) = spng::decode(TEST_PNG_005, spng::Format::Rgba8)?;
+    let mut arena = vec![0; 256 * 1024];
+    let mut output = [0; 4 * 4 * 4];
+    let info = spng::decode_with_arena(TEST_PNG_005, spng::Format::Rgba8, &mut arena, &mut output)?;
+    assert_eq!(expected_info, info);
+    assert_eq!(expected, output);
+
+    let mut arena = [0; 1024];
+    let result =
+        spng::decode_with_arena(TEST_PNG_005, spng::Format::Rgba8, &mut arena, &mut output);
+    assert_eq!(Err(spng::Error::Mem), result);
+    Ok(())
+}
+
+#[test]
+fn encode_reserve() -> Result<(), Box<dyn std::error::Error>> {
+    let (out_info, data) = spng::decode(TEST_PNG_001, spng::Format::Rgba8)?;
+    let mut encoder = spng::Encoder::new(Vec::new(), 0, 0).with_info(&out_info);
+    encoder.reserve(TEST_PNG_001.len());
+    let mut writer = encoder.write_info()?;
+    writer.write_image_data(&data)?;
+    let png = writer.finish()?;
+    assert!(png.capacity() >= TEST_PNG_001.len());
+    assert_eq!(
+        (out_info, data),
+        spng::decode(png.as_slice(), spng::Format::Rgba8)?
+    );
+    Ok(())
+}
+
+#[test]
+fn info_try_from_ihdr() -> Result<(), Box<dyn std::error::Error>> {
+    use std::convert::TryFrom;
+    let mut ctx = spng::raw::RawContext::new()?;
+    ctx.set_png_buffer(TEST_PNG_001)?;
+    let ihdr = ctx.get_ihdr()?;
+    let info = spng::Info::try_from(&ihdr)?;
+    assert_eq!(Decoder::new(TEST_PNG_001).read_info()?.info(), info);
+
+    let buffer_size = ctx.decoded_image_size(spng::Format::Rgb8)?;
+    let out_info =
+        spng::OutputInfo::from_ihdr_format_buffer_size(&ihdr, spng::Format::Rgb8, buffer_size)?;
+    assert_eq!(spng::decode(TEST_PNG_001, spng::Format::Rgb8)?.0, out_info);
+
+    let mut invalid = ihdr;
+    invalid.color_type = 5;
+    assert_eq!(Err(spng::Error::ColorType), spng::Info::try_from(&invalid));
+    Ok(())
+}
+
+#[test]
+fn flags_display() {
+    use spng::{DecodeFlags, EncodeFlags};
+    let decode_flags = DecodeFlags::TRANSPARENCY | DecodeFlags::GAMMA;
+    assert_eq!("TRANSPARENCY | GAMMA", decode_flags.to_string());
+    assert_eq!("", DecodeFlags::empty().to_string());
+    let encode_flags = EncodeFlags::PROGRESSIVE | EncodeFlags::FINALIZE;
+    assert_eq!("PROGRESSIVE | FINALIZE", encode_flags.to_string());
+}
+
+#[test]
+fn error_message() {
+    let message = spng::Error::Signature.message();
+    assert!(!message.is_empty());
+    assert_eq!(message, spng::Error::Signature.message());
+    assert_eq!(message, spng::Error::Signature.to_string());
+    assert_ne!(message, spng::Error::ChunkCrc.message());
+}
+
 #[test]
 fn version() {
     println!("{:?}", spng::version());
 }
+
+#[test]
+fn simd_level() {
+    println!("{}", spng::simd_level());
+    assert!(["sse4.1", "ssse3", "sse2", "neon", "none"].contains(&spng::simd_level()));
+}
+
+#[test]
+fn encoder_available() {
+    assert!(spng::encoder_available());
+}
+
+#[test]
+fn version_str() {
+    let (major, minor, patch) = spng::version();
+    let numeric = format!("{}.{}.{}", major, minor, patch);
+    assert!(spng::version_str().starts_with(&numeric));
+}